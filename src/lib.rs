@@ -1,49 +1,43 @@
-use std::sync::{Arc, Mutex};
-use std::net::IpAddr;
-use net::{QueryResult, nbns::NbnsQuery, mdns::MdnsQuery};
-use clap::Parser;
-use utils::AppendNewline;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::Duration;
+use std::sync::Arc;
+use net::{Session, nbns::NbnsQuery, mdns::MdnsQuery, llmnr::LlmnrQuery, listen::{Heard, Listener}, neighbors::{NeighborCache, neighbor_table}};
+
+pub use net::{QueryResult, NameLookup, MacAddress, MacSource, Protocol, Column, nbns::{HostRole, NbAddress, NbnsAnswer, NbnsService, NbnsStatistics, NetbiosName, NodeType}};
+pub use net::dns;
+pub use oui::OuiDatabase;
 
 mod net;
+mod oui;
 mod utils;
 
-#[derive(Parser, Clone)]
-#[command(version, about, long_about = None)]
-pub struct Args {
-    /// Target to ask hostname, can be
-    /// address (192.168.1.100) or range (192.168.1.0/24)
-    target: String,
-
-    /// Verbose output
-    #[arg(short, long)]
-    verbose: bool,
-
-    /// Quieter output
-    #[arg(short, long)]
-    quiet: bool,
-
-    /// Wait for all answers, and then print them at once
-    #[arg(short, long)]
-    wait: bool,
-
-    /// Timeout in milliseconds
-    #[arg(short, long)]
-    timeout: Option<u64>,
-}
-
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum AppError {
     ParseAddress,
     ParseAddressesRange,
+    ParseColumn,
+    ParseProtocol,
+    ParseName,
+    ParseScopeId,
+    MissingScopeId,
     SocketCreate,
     SocketConnect,
     SocketSend,
+    ListenBind,
+    ListenReceive,
     SocketTimeout,
     InvalidResponseNbns,
     InvalidResponseMdns,
+    InvalidResponseLlmnr,
     InvalidResponses,
     ScanError,
-    Ipv6,
+    InvalidLimit,
+    NoProtocols,
+    BroadcastRange,
+    BroadcastProtocol,
+    RuntimeCreate,
+    LoadOui,
+    NameNotFound,
 }
 impl std::error::Error for AppError {}
 impl std::fmt::Display for AppError {
@@ -51,202 +45,489 @@ impl std::fmt::Display for AppError {
         write!(f, "{}", match self {
             AppError::ParseAddress => "failed to parse address",
             AppError::ParseAddressesRange => "failed to parse address range",
+            AppError::ParseProtocol => "unknown protocol, expected nbns, mdns or llmnr",
+            AppError::ParseName => "failed to parse NetBIOS name, expected up to 15 characters with optional suffix, like FILESRV01#20",
+            AppError::ParseScopeId => "unknown network interface in scope ID",
+            AppError::MissingScopeId => "link-local IPv6 address requires scope ID, like fe80::1%eth0",
+            AppError::ParseColumn => "unknown column, expected mac, mac_source, vendor, workgroup, names, roles or protocols",
             AppError::SocketCreate => "failed to create socket",
             AppError::SocketConnect => "connection with remote host failed",
             AppError::SocketSend => "failed to send request",
+            AppError::ListenBind => "failed to bind port for listening, NetBIOS port 137 usually requires root",
+            AppError::ListenReceive => "failed to receive traffic while listening",
             AppError::SocketTimeout => "invalid socket timeout",
             AppError::InvalidResponseNbns => "recived invalid Nbns response",
             AppError::InvalidResponseMdns => "recived invalid mDNS response",
+            AppError::InvalidResponseLlmnr => "recived invalid LLMNR response",
             AppError::InvalidResponses => "recived multiple invalid responses",
             AppError::ScanError => "errors occurred while scanning range of addresses",
-            AppError::Ipv6 => "IPv6 is not supported yet",
+            AppError::InvalidLimit => "limit of queries or packet rate can't be zero",
+            AppError::NoProtocols => "at least one protocol should be used",
+            AppError::BroadcastRange => "broadcast discovery requires IPv4 range, like 192.168.1.0/24",
+            AppError::BroadcastProtocol => "broadcast discovery requires nbns in --protocols",
+            AppError::RuntimeCreate => "failed to start async runtime",
+            AppError::LoadOui => "failed to read OUI database file",
+            AppError::NameNotFound => "nobody answered to name query",
         })
     }
 }
 
-/// When the program is run with `--wait` flag, it doesn't output immediately and stores everything in
-/// `OutputBuffer`.
-/// It's a `String` contained in `Arc<Mutex>`, so it can be shared between async threads.
-#[derive(Clone)]
-struct OutputBuffer (
-    Arc<Mutex<String>>
-);
-impl OutputBuffer {
-    fn new(ip_addr_type: IpAddr, args: &Args) -> Self {
-        let mut s = Self ( Arc::new(Mutex::new(String::new())) );
-        if !(args.quiet || args.verbose) {
-            s.write(&QueryResult::table_head(&ip_addr_type), args.wait);
+/// What should be asked for names: single address (192.168.1.100), IPv6 address with scope ID
+/// (fe80::1%eth0) or range (192.168.1.0/24, fe80::/64%eth0).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Host(IpAddr),
+    /// IPv6 address with index of network interface
+    ScopedHost(Ipv6Addr, u32),
+    Range(ipnet::IpNet),
+    /// IPv6 range with index of network interface
+    ScopedRange(ipnet::Ipv6Net, u32),
+}
+impl Target {
+    /// Address of the host, or network address of the range.
+    pub fn addr(&self) -> IpAddr {
+        match self {
+            Target::Host(addr) => *addr,
+            Target::ScopedHost(addr, _) => IpAddr::V6(*addr),
+            Target::Range(range) => range.addr(),
+            Target::ScopedRange(range, _) => IpAddr::V6(range.addr()),
         }
-        s
     }
-    fn write(&mut self, s: &str, wait: bool) {
-        if wait {
-            let mut b = self.0.lock().unwrap();
-            b.push_str(s);
-            b.new_line();
+}
+impl std::str::FromStr for Target {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some((addr, scope)) = s.split_once('%') {
+            let scope_id = net::interface_index(scope).ok_or(AppError::ParseScopeId)?;
+            if addr.contains('/') {
+                let range = addr.parse().map_err(|_| AppError::ParseAddressesRange)?;
+                Ok(Target::ScopedRange(range, scope_id))
+            } else {
+                let addr = addr.parse().map_err(|_| AppError::ParseAddress)?;
+                Ok(Target::ScopedHost(addr, scope_id))
+            }
+        } else if s.contains('/') {
+            s.parse().map(Target::Range).map_err(|_| AppError::ParseAddressesRange)
         } else {
-            println!("{}", s);
+            s.parse().map(Target::Host).map_err(|_| AppError::ParseAddress)
         }
     }
 }
 
-/// Main struct. Contains `Args` and `OutputBuffer`.
-/// `ask` and `ask_multiple` will ask for hostnames and domain name and output it to STDOUT or
-/// `OutputBuffer` when `--wait` option is set.
-/// On `drop` will print `OutputBuffer`, if should.
-struct App {
-    args: Args,
-    output_buffer: OutputBuffer,
+/// Settings used by `Resolver` for every query.
+#[derive(Debug, Clone)]
+pub struct ScanConfig {
+    /// How long to wait for an answer to NetBIOS request
+    pub nbns_timeout: Duration,
+    /// How long to wait for an answer to mDNS request
+    pub mdns_timeout: Duration,
+    /// How long to wait for an answer to LLMNR request
+    pub llmnr_timeout: Duration,
+    /// How many times unanswered request is sent again
+    pub retries: u32,
+    /// Delay before the first retry, every next retry waits twice longer
+    pub retry_backoff: Duration,
+    /// Maximum amount of hosts queried at the same time while scanning range, `None` is unlimited
+    pub max_in_flight: Option<usize>,
+    /// Maximum amount of packets sent per second by all protocols, `None` is unlimited
+    pub rate: Option<u32>,
+    /// Send mDNS questions to multicast group instead of the host, see `MdnsQuery::send_multicast`
+    pub mdns_multicast: bool,
+    /// Protocols used for querying hosts, NBNS is used only for IPv4 hosts
+    pub protocols: Vec<Protocol>,
+    /// File with vendors of MAC addresses, added to the compiled-in list, see `OuiDatabase::load`
+    pub oui_file: Option<std::path::PathBuf>,
 }
-impl App {
-    fn new(args: Args) -> Result<Self, AppError> {
-        let ip_addr_type: IpAddr = if args.target.contains('/') {
-            args.target.parse::<ipnet::IpNet>()
-                .map_err(|_| AppError::ParseAddressesRange)?
-                .addr()
-        } else {
-            args.target.parse()
-                .map_err(|_| AppError::ParseAddress)?
-        };
-        if let Some(new_timeout) = args.timeout {
-            match new_timeout {
-                0 ..= net::TOO_LOW_TIMEOUT_WARNING_MS => { eprintln!("The selected timeout may be too low for reciving answers")},
-                net::TOO_BIG_TIMEOUT_WARNING_MS..     => { eprintln!("The selected timeout may be too big and scanning may be slow")},
-                _ => {}
-            }
-            if let Err(e) = net::set_timeout_from_millis(new_timeout) {
-                return Err(e)
-            };
+impl ScanConfig {
+    pub const DEFAULT_TIMEOUT_MS: u64 = 500;
+    pub const DEFAULT_MAX_IN_FLIGHT: usize = 256;
+    pub const DEFAULT_RETRY_BACKOFF_MS: u64 = 100;
+}
+impl Default for ScanConfig {
+    fn default() -> Self {
+        ScanConfig {
+            nbns_timeout: Duration::from_millis(Self::DEFAULT_TIMEOUT_MS),
+            mdns_timeout: Duration::from_millis(Self::DEFAULT_TIMEOUT_MS),
+            llmnr_timeout: Duration::from_millis(Self::DEFAULT_TIMEOUT_MS),
+            retries: 0,
+            retry_backoff: Duration::from_millis(Self::DEFAULT_RETRY_BACKOFF_MS),
+            max_in_flight: Some(Self::DEFAULT_MAX_IN_FLIGHT),
+            rate: None,
+            mdns_multicast: false,
+            protocols: Protocol::ALL.to_vec(),
+            oui_file: None,
         }
+    }
+}
 
-        Ok(App {
-                output_buffer: OutputBuffer::new(ip_addr_type, &args),
-                args,
-            })
+/// Asks hosts for their NetBIOS names, mDNS domain names and LLMNR names.
+/// Doesn't write anything to STDOUT or STDERR, all results and errors are returned to the caller.
+/// Queries are made on own async runtime, so `Resolver` shouldn't be used inside of other async runtime.
+///
+/// ```no_run
+/// use askhostname::{Resolver, ScanConfig};
+///
+/// let resolver = Resolver::new(ScanConfig::default()).unwrap();
+/// for result in resolver.scan("192.168.1.0/24".parse().unwrap()).unwrap() {
+///     if !result.is_empty() {
+///         println!("{}", result.table_row());
+///     }
+/// }
+/// ```
+pub struct Resolver {
+    config: ScanConfig,
+    oui: OuiDatabase,
+    runtime: tokio::runtime::Runtime,
+}
+impl Resolver {
+    /// Maximum amount of hosts remembered by `Listening::run_with`
+    pub const MAX_LISTENED_HOSTS: usize = 4096;
+
+    /// Constructs `Resolver`. Returns `AppError::SocketTimeout` if any of timeouts is zero,
+    /// `AppError::InvalidLimit` if any of limits is zero, `AppError::NoProtocols` if list of protocols
+    /// is empty and `AppError::LoadOui` if `oui_file` can't be read.
+    pub fn new(config: ScanConfig) -> Result<Self, AppError> {
+        if config.nbns_timeout.is_zero() || config.mdns_timeout.is_zero() || config.llmnr_timeout.is_zero() {
+            return Err(AppError::SocketTimeout);
+        }
+        if config.max_in_flight == Some(0) || config.rate == Some(0) {
+            return Err(AppError::InvalidLimit);
+        }
+        if config.protocols.is_empty() {
+            return Err(AppError::NoProtocols);
+        }
+        let oui = match &config.oui_file {
+            Some(path) => OuiDatabase::load(path)?,
+            None => OuiDatabase::builtin(),
+        };
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .map_err(|_| AppError::RuntimeCreate)?;
+
+        Ok(Resolver { config, oui, runtime })
     }
-    fn target(&self) -> &str {
-        &self.args.target
+    pub fn config(&self) -> &ScanConfig {
+        &self.config
     }
 
-    // borrowing rules doesn't allow moving self to aync block, so query_and_out supossed to be
-    // wrapped in ask and ask_multiple functions.
-    // Instead of copying self, ask_multiple copies Arc<Mutex<OutputBuffer>> and passes it to query_and_out.
-    fn query_and_out(addr: IpAddr, mut out: OutputBuffer, wait: bool, verbose: bool) -> Result<(), AppError> {
+    /// Asks `addr` by every protocol of `ScanConfig::protocols` at the same time, so unanswering
+    /// host costs only the longest timeout.
+    async fn query(addr: IpAddr, scope_id: u32, session: &Session) -> QueryResult {
+        let mut result = QueryResult::new(addr);
 
-        if addr.is_ipv6() { return Err(AppError::Ipv6) };
+        let uses = |protocol| session.config.protocols.contains(&protocol);
 
-        let mut result = QueryResult::new(addr);
-        let mut errors: Vec<AppError> = Vec::new();
-
-        if addr.is_ipv4() { // Nbns doesn't support IPv6
-            match NbnsQuery::send(addr) {
-                Ok(Some(ans)) => {
-                    for i in ans {
-                        result.push_hostname(i);
-                    };
-                },
-                Ok(None) => {}
-                Err(e) => {
-                    errors.push(e);
-                },
-            };
-        }
+        let nbns = async {
+            // Nbns doesn't support IPv6
+            if addr.is_ipv4() && uses(Protocol::Nbns) { NbnsQuery::send(addr, session).await } else { Ok(None) }
+        };
+        let mdns = async {
+            if uses(Protocol::Mdns) { MdnsQuery::send(addr, scope_id, session).await } else { Ok(None) }
+        };
+        let llmnr = async {
+            if uses(Protocol::Llmnr) { LlmnrQuery::send(addr, scope_id, session).await } else { Ok(None) }
+        };
+        let (nbns, mdns, llmnr) = tokio::join!(nbns, mdns, llmnr);
 
-        match MdnsQuery::send(addr) {
-            Ok(Some(ans)) => {
-                result.set_domain_name(ans.to_string());
+        match nbns {
+            Ok(Some((status, attempts))) => {
+                result.set_node_status(status);
+                result.set_attempts(Protocol::Nbns, attempts);
+            },
+            Ok(None) => {}
+            Err(e) => {
+                result.push_error(e);
+            },
+        };
+
+        match mdns {
+            Ok(Some((ans, attempts))) => {
+                result.set_domain_name(ans);
+                result.set_attempts(Protocol::Mdns, attempts);
             },
             Ok(None) => {},
             Err(e) => {
-                errors.push(e);
+                result.push_error(e);
             }
         }
 
-        if !result.is_empty() {
-            if verbose {
-                out.write(&result.verbose_entry(), wait);
-            } else {
-                out.write(&result.table_row(), wait);
+        match llmnr {
+            Ok(Some((ans, attempts))) => {
+                result.set_llmnr_name(ans);
+                result.set_attempts(Protocol::Llmnr, attempts);
+            },
+            Ok(None) => {},
+            Err(e) => {
+                result.push_error(e);
             }
         }
 
-        if !errors.is_empty() {
-            if errors.len() == 1 {
-                return Err(*errors.first().unwrap());
-            } else {
-                return Err(AppError::InvalidResponses)
+        result
+    }
+    /// Completes `result` with information, which isn't asked from the host: MAC address from kernel
+    /// neighbor table, if the host answered but didn't report it, and vendor of the adapter.
+    /// Answered host is usually in the table after exchanging packets with it.
+    /// `neighbors` should be shared by every host of one scan.
+    fn finish(&self, mut result: QueryResult, neighbors: &mut NeighborCache) -> QueryResult {
+        if !result.is_empty() && result.mac_address().is_none() {
+            if let Some(mac) = neighbors.mac(result.ip_addr()) {
+                result.set_neighbor_mac(mac);
             }
         }
+        if let Some(vendor) = result.mac_address().and_then(|m| self.oui.lookup(m)) {
+            result.set_vendor(vendor.to_string());
+        }
+        result
+    }
+    /// Asks `addr` for any names.
+    /// Errors of separate protocols are stored in returned `QueryResult`, see `QueryResult::error`.
+    /// Link-local IPv6 addresses require scope ID, use `resolve_scoped` for them.
+    pub fn resolve(&self, addr: IpAddr) -> Result<QueryResult, AppError> {
+        match addr {
+            IpAddr::V6(a) if is_link_local(&a) => Err(AppError::MissingScopeId),
+            _ => self.resolve_with_scope(addr, 0),
+        }
+    }
+    /// Asks IPv6 `addr` on network interface with `scope_id` index for any names.
+    pub fn resolve_scoped(&self, addr: Ipv6Addr, scope_id: u32) -> Result<QueryResult, AppError> {
+        self.resolve_with_scope(IpAddr::V6(addr), scope_id)
+    }
+    fn resolve_with_scope(&self, addr: IpAddr, scope_id: u32) -> Result<QueryResult, AppError> {
+        self.runtime.block_on(async {
+            let session = Session::new(&self.config).await?;
+            let result = Self::query(addr, scope_id, &session).await;
+            Ok(self.finish(result, &mut NeighborCache::new()))
+        })
+    }
+    /// Finds addresses of `name` owners by NetBIOS NAME QUERY REQUEST to WINS `server`, or by
+    /// broadcast to the local network if it's `None`, and by LLMNR multicast query. Only protocols
+    /// of `ScanConfig::protocols` are used. Returned lists are empty if nobody answered.
+    /// LLMNR failure is returned as error only if NBNS found nothing.
+    pub fn lookup_name(&self, name: &NetbiosName, server: Option<Ipv4Addr>) -> Result<NameLookup, AppError> {
+        self.runtime.block_on(async {
+            let session = Session::new(&self.config).await?;
+            let mut lookup = NameLookup { name: name.clone(), nbns: Vec::new(), llmnr: Vec::new() };
+            if self.config.protocols.contains(&Protocol::Nbns) {
+                lookup.nbns = NbnsQuery::resolve_name(name, server, &session).await?;
+            }
+            if self.config.protocols.contains(&Protocol::Llmnr) {
+                match LlmnrQuery::resolve_name(&name.name, &session).await {
+                    Ok(addresses) => lookup.llmnr = addresses,
+                    // NBNS answers are still worth returning
+                    Err(_) if !lookup.nbns.is_empty() => {},
+                    Err(e) => return Err(e),
+                }
+            }
+            Ok(lookup)
+        })
+    }
+    /// Asks every host in `addr_range` and returns results for all of them, including hosts that
+    /// didn't answer (`QueryResult::is_empty`).
+    pub fn scan(&self, addr_range: ipnet::IpNet) -> Result<Vec<QueryResult>, AppError> {
+        let mut results = Vec::new();
+        self.scan_with(addr_range, |r| results.push(r))?;
+        Ok(results)
+    }
+    /// Asynchronously asks every host in `addr_range` and calls `on_result` for each of them as soon
+    /// as it's done, so results come in order of completion. Returns after all hosts were asked.
+    /// No more than `ScanConfig::max_in_flight` hosts are asked at the same time.
+    /// IPv6 networks are too big to be swept, so only hosts of `addr_range` found in kernel neighbor
+    /// table or answered to multicast mDNS query are asked, see `scan_scoped_with`.
+    pub fn scan_with<F>(&self, addr_range: ipnet::IpNet, on_result: F) -> Result<(), AppError>
+    where F: FnMut(QueryResult)
+    {
+        self.scan_range(addr_range, 0, on_result)
+    }
+    /// Same as `scan_with`, but IPv6 hosts are discovered only on network interface with `scope_id` index.
+    pub fn scan_scoped_with<F>(&self, addr_range: ipnet::Ipv6Net, scope_id: u32, on_result: F) -> Result<(), AppError>
+    where F: FnMut(QueryResult)
+    {
+        self.scan_range(addr_range.into(), scope_id, on_result)
+    }
+    /// Discovers hosts of `addr_range` by single NetBIOS node status request sent to its broadcast
+    /// address, and calls `on_result` for every host, which answered, as soon as it's done.
+    /// Only NBNS is used, so hosts without NetBIOS aren't found. Returns after NBNS timeout.
+    /// Returns `AppError::BroadcastProtocol` if NBNS isn't in `ScanConfig::protocols`.
+    pub fn broadcast_with<F>(&self, addr_range: ipnet::Ipv4Net, mut on_result: F) -> Result<(), AppError>
+    where F: FnMut(QueryResult)
+    {
+        if !self.config.protocols.contains(&Protocol::Nbns) { return Err(AppError::BroadcastProtocol) };
 
-        Ok(())
-    }
-    /// Asks `addr` for any names and outputs them to STDOUT or to `OutputBuffer` when `self.wait` is true.
-    /// If `self.verbose` is true, will verbosely format entry.
-    /// When querying resulted an error, will return `AppError`.
-    fn ask(&mut self, addr: IpAddr) -> Result<(), AppError> {
-        Self::query_and_out(addr, self.output_buffer.clone(), self.args.wait, self.args.verbose)
-    }
-    /// Asynchronously asks every host in `addr_range` and outputs results to STDOUT or
-    /// `OutputBuffer` if `self.wait` is true.
-    /// When any of querying resulted an error, will print address and error to STDERR and return `AppError::ScanError`
-    fn ask_multiple(&mut self, addr_range: ipnet::IpNet) -> Result<(), AppError> {
-        if addr_range.addr().is_ipv6() { return Err(AppError::ScanError) };
-        // the only case where async is needed is in this function
-        let rt = tokio::runtime::Builder::new_multi_thread()
-            .enable_all()
-            .build()
-            .unwrap();
-
-        let errors: Arc<Mutex<Vec<(IpAddr, AppError)>>> = Arc::new(Mutex::new(Vec::new()));
-
-        for addr in addr_range.hosts() {
-            let b = self.output_buffer.clone(); // Rc<Mutex>
-            let wait = self.args.wait;
-            let verbose = self.args.verbose;
-            let err_vec = errors.clone();
-            rt.spawn_blocking(move || {
-                if let Err(e) = Self::query_and_out(addr, b, wait, verbose) {
-                    err_vec.lock().unwrap().push((addr, e));
-                };
-            });
-        };
+        self.runtime.block_on(async {
+            let session = Session::new(&self.config).await?;
+            let mut neighbors = NeighborCache::new();
+            NbnsQuery::broadcast(addr_range.broadcast(), &session, |addr, status, attempts| {
+                // broadcast may be answered by hosts of bigger subnet
+                if !addr_range.contains(&addr) { return };
+
+                let mut result = QueryResult::new(IpAddr::V4(addr));
+                match status {
+                    Ok(status) => {
+                        result.set_node_status(status);
+                        result.set_attempts(Protocol::Nbns, attempts);
+                    },
+                    Err(e) => result.push_error(e),
+                }
+                on_result(self.finish(result, &mut neighbors));
+            }).await
+        })
+    }
+    /// Binds ports for passive listening to mDNS and NetBIOS traffic of other hosts, which is
+    /// started by `Listening::run_with`. Only protocols of `ScanConfig::protocols` are listened,
+    /// LLMNR isn't supported. Returns `AppError::NoProtocols` if neither of them is used and
+    /// `AppError::ListenBind` if ports can't be bound.
+    pub fn listen(&self) -> Result<Listening<'_>, AppError> {
+        let uses = |protocol| self.config.protocols.contains(&protocol);
+        if !uses(Protocol::Mdns) && !uses(Protocol::Nbns) { return Err(AppError::NoProtocols) };
 
-        let errors = errors.lock().unwrap();
-        if !errors.is_empty() {
-            for (addr, err) in errors.iter() {
-                eprintln!("Error for {}: {}", addr, err);
+        let listener = self.runtime.block_on(async { Listener::bind(uses(Protocol::Mdns), uses(Protocol::Nbns)) })?;
+        Ok(Listening { resolver: self, listener })
+    }
+    /// Binds ports by `listen` and listens for `duration`, see `Listening::run_with`.
+    pub fn listen_with<F>(&self, duration: Option<Duration>, on_result: F) -> Result<(), AppError>
+    where F: FnMut(QueryResult)
+    {
+        self.listen()?.run_with(duration, on_result)
+    }
+    fn scan_range<F>(&self, addr_range: ipnet::IpNet, scope_id: u32, mut on_result: F) -> Result<(), AppError>
+    where F: FnMut(QueryResult)
+    {
+        self.runtime.block_on(async {
+            // every NBNS request is sent from one socket
+            let session = Arc::new(Session::new(&self.config).await?);
+
+            // IPv4 range may be huge, so its hosts are generated lazily
+            let hosts: Box<dyn Iterator<Item = (IpAddr, u32)>> = match addr_range {
+                ipnet::IpNet::V4(range) => Box::new(range.hosts().map(|a| (IpAddr::V4(a), 0))),
+                ipnet::IpNet::V6(range) => Box::new(Self::discover_v6(range, scope_id, &session).await.into_iter()),
+            };
+
+            let mut neighbors = NeighborCache::new();
+            let mut tasks = tokio::task::JoinSet::new();
+            for (addr, host_scope_id) in hosts {
+                if self.config.max_in_flight.is_some_and(|max| tasks.len() >= max) {
+                    if let Some(Ok(result)) = tasks.join_next().await {
+                        on_result(self.finish(result, &mut neighbors));
+                    }
+                }
+
+                let session = session.clone();
+                tasks.spawn(async move { Self::query(addr, host_scope_id, &session).await });
             }
-            return Err(AppError::ScanError);
-        }
 
-        Ok(())
+            while let Some(joined) = tasks.join_next().await {
+                // tasks are never aborted and query doesn't panic
+                if let Ok(result) = joined {
+                    on_result(self.finish(result, &mut neighbors));
+                }
+            }
+            Ok(())
+        })
     }
-}
-impl Drop for App {
-    fn drop(&mut self) {
-        if self.args.wait {
-            println!("{}", self.output_buffer.0.lock().unwrap())
+    /// Finds hosts of `addr_range` in kernel neighbor table and by multicast mDNS query.
+    /// Returns addresses with scope ID, which is not zero only for link-local addresses.
+    /// If `scope_id` is not zero, hosts on other network interfaces are skipped.
+    /// Multicast query may fail on interfaces without multicast, then only neighbor table is used.
+    /// It's skipped too, if mDNS isn't in `ScanConfig::protocols`.
+    async fn discover_v6(addr_range: ipnet::Ipv6Net, scope_id: u32, session: &Session) -> Vec<(IpAddr, u32)> {
+        let mut found: Vec<(Ipv6Addr, u32)> = neighbor_table().into_iter()
+            .filter_map(|n| match n.addr {
+                IpAddr::V6(a) => Some((a, n.ifindex)),
+                IpAddr::V4(_) => None,
+            })
+            .collect();
+        if session.config.protocols.contains(&Protocol::Mdns) {
+            if let Ok(answered) = MdnsQuery::discover_v6(scope_id, session).await {
+                found.extend(answered.into_iter().map(|a| (*a.ip(), a.scope_id())));
+            }
         }
+
+        let mut hosts = Vec::new();
+        for (addr, ifindex) in found {
+            if !addr_range.contains(&addr) || addr.is_multicast() { continue };
+            if scope_id != 0 && ifindex != scope_id { continue };
+
+            let host = (IpAddr::V6(addr), if is_link_local(&addr) { ifindex } else { 0 });
+            if !hosts.contains(&host) { hosts.push(host) };
+        }
+
+        hosts
     }
 }
 
-pub fn run(args: Args) -> Result<(), AppError> {
-
-    let mut app = App::new(args)?;
+/// Ports bound by `Resolver::listen`, which receive traffic of other hosts.
+pub struct Listening<'a> {
+    resolver: &'a Resolver,
+    listener: Listener,
+}
+impl Listening<'_> {
+    /// Passively listens to mDNS and NetBIOS traffic of other hosts and never sends anything.
+    /// `on_result` is called with everything known about the host every time it's heard with new names.
+    /// No more than `Resolver::MAX_LISTENED_HOSTS` hosts are remembered, the least recently heard
+    /// one is forgotten for a new one, and reported again if it's heard later.
+    /// Returns after `duration`, or when the process is interrupted by Ctrl+C.
+    /// Returns `AppError::ListenReceive` if receiving fails before that.
+    pub fn run_with<F>(self, duration: Option<Duration>, mut on_result: F) -> Result<(), AppError>
+    where F: FnMut(QueryResult)
+    {
+        let Listening { resolver, listener } = self;
+        resolver.runtime.block_on(async {
+            let mut neighbors = NeighborCache::new();
+            // every host with the time it was heard last
+            let mut hosts: std::collections::HashMap<IpAddr, (QueryResult, tokio::time::Instant)> = std::collections::HashMap::new();
+            let listening = listener.run(|heard| {
+                let (addr, protocol) = match &heard {
+                    Heard::NbnsName(addr, _) | Heard::NodeStatus(addr, _) => (*addr, Protocol::Nbns),
+                    Heard::DomainName(addr, _) => (*addr, Protocol::Mdns),
+                };
+                if addr.is_unspecified() { return };
 
-    if !app.target().contains('/') {
-        let addr: IpAddr = app.target()
-            .parse()
-            .map_err(|_| AppError::ParseAddress)?;
+                if !hosts.contains_key(&addr) && hosts.len() >= Resolver::MAX_LISTENED_HOSTS {
+                    let oldest = hosts.iter().min_by_key(|(_, (_, heard_at))| *heard_at).map(|(a, _)| *a);
+                    if let Some(oldest) = oldest { hosts.remove(&oldest); }
+                }
+                let (result, heard_at) = hosts.entry(addr).or_insert_with(|| (QueryResult::new(addr), tokio::time::Instant::now()));
+                *heard_at = tokio::time::Instant::now();
 
-        app.ask(addr)?;
-    } else {
-        let range: ipnet::IpNet = app.target()
-            .parse()
-            .map_err(|_| AppError::ParseAddressesRange)?;
+                let changed = match heard {
+                    Heard::NbnsName(_, name) => result.add_hostname(name),
+                    Heard::NodeStatus(_, status) => {
+                        let mut changed = false;
+                        for name in status.names {
+                            changed |= result.add_hostname(name);
+                        }
+                        changed
+                    },
+                    Heard::DomainName(_, name) if result.domain_name() != Some(name.as_str()) => {
+                        result.set_domain_name(name);
+                        true
+                    },
+                    Heard::DomainName(..) => false,
+                };
+                if changed {
+                    result.add_protocol(protocol);
+                    on_result(resolver.finish(result.clone(), &mut neighbors));
+                }
+            });
 
-        app.ask_multiple(range.into())?;
+            let until = async {
+                match duration {
+                    Some(duration) => tokio::time::sleep(duration).await,
+                    None => std::future::pending().await,
+                }
+            };
+            tokio::select! {
+                result = listening => result,
+                _ = until => Ok(()),
+                _ = tokio::signal::ctrl_c() => Ok(()),
+            }
+        })
     }
+}
 
-    Ok(())
+/// Link-local IPv6 addresses (fe80::/10) are meaningless without network interface.
+fn is_link_local(addr: &Ipv6Addr) -> bool {
+    addr.segments()[0] & 0xffc0 == 0xfe80
 }
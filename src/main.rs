@@ -1,5 +1,404 @@
-use askhostname::{run, Args};
+use askhostname::{AppError, Column, NameLookup, NetbiosName, Protocol, QueryResult, Resolver, ScanConfig, Target};
 use clap::Parser;
+use std::time::Duration;
+
+const TOO_LOW_TIMEOUT_WARNING_MS: u64 = 100;
+const TOO_BIG_TIMEOUT_WARNING_MS: u64 = 3500;
+
+#[derive(clap::ValueEnum, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    /// Table, or verbose entries with --verbose
+    Table,
+    /// Single JSON document, printed after all hosts were asked
+    Json,
+    /// JSON object per line, printed as soon as host answers, errors included
+    Ndjson,
+    /// Comma-separated values with header
+    Csv,
+    /// Tab-separated values with header
+    Tsv,
+}
+impl Format {
+    fn separator(&self) -> char {
+        match self {
+            Format::Tsv => '\t',
+            _ => ',',
+        }
+    }
+}
+
+#[derive(clap::Subcommand, Clone, Copy)]
+enum Command {
+    /// Passively collect names from mDNS and NetBIOS traffic of other hosts, without sending anything.
+    /// Hosts are printed as soon as they are heard, and again when new names are heard.
+    /// Binding NetBIOS port 137 usually requires root
+    Listen {
+        /// Stop listening after this many seconds, instead of on Ctrl+C
+        #[arg(short, long, value_name = "SECONDS")]
+        duration: Option<u64>,
+    },
+}
+
+#[derive(Parser, Clone)]
+#[command(version, about, long_about = None, subcommand_negates_reqs = true)]
+struct Args {
+    /// Target to ask hostname, can be
+    /// address (192.168.1.100, fe80::1%eth0) or range (192.168.1.0/24, fe80::/64%eth0).
+    /// Hosts of IPv6 range are found in neighbor table and by multicast mDNS query.
+    /// NetBIOS name with optional suffix (FILESRV01, FILESRV01#20) is resolved to addresses by NetBIOS and LLMNR
+    #[arg(required = true)]
+    target: Option<String>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Discover hosts of IPv4 range by single NetBIOS request to its broadcast address.
+    /// Much faster, but hosts without NetBIOS aren't found and mDNS isn't asked. Requires nbns in --protocols
+    #[arg(short, long)]
+    broadcast: bool,
+
+    /// WINS server to resolve NetBIOS name, instead of broadcast
+    #[arg(long, value_name = "ADDRESS")]
+    wins: Option<std::net::Ipv4Addr>,
+
+    /// Output format
+    #[arg(short, long, value_enum, default_value_t = Format::Table, global = true)]
+    format: Format,
+
+    /// Additional columns of CSV and TSV output: mac, mac_source, vendor, workgroup, names, roles, protocols
+    #[arg(long, value_delimiter = ',', global = true)]
+    columns: Vec<Column>,
+
+    /// File with vendors of MAC addresses in IEEE oui.txt or Wireshark manuf format,
+    /// used in addition to the compiled-in list
+    #[arg(long, value_name = "PATH", global = true)]
+    oui_file: Option<std::path::PathBuf>,
+
+    /// Send mDNS questions to multicast group with unicast response requested, for responders
+    /// ignoring direct queries
+    #[arg(long)]
+    mdns_multicast: bool,
+
+    /// Protocols used for querying: nbns, mdns, llmnr
+    #[arg(short, long, value_delimiter = ',', default_value = "nbns,mdns,llmnr", global = true)]
+    protocols: Vec<Protocol>,
+
+    /// Show only hosts in this workgroup or NT domain, errors of other hosts aren't reported
+    #[arg(long, value_name = "NAME", global = true)]
+    workgroup: Option<String>,
+
+    /// Verbose output
+    #[arg(short, long, global = true)]
+    verbose: bool,
+
+    /// Quieter output
+    #[arg(short, long, global = true)]
+    quiet: bool,
+
+    /// Wait for all answers, and then print them at once
+    #[arg(short, long, global = true)]
+    wait: bool,
+
+    /// Timeout in milliseconds for every protocol
+    #[arg(short, long)]
+    timeout: Option<u64>,
+
+    /// Timeout in milliseconds for NetBIOS, overrides --timeout
+    #[arg(long, value_name = "TIMEOUT")]
+    nbns_timeout: Option<u64>,
+
+    /// Timeout in milliseconds for mDNS, overrides --timeout
+    #[arg(long, value_name = "TIMEOUT")]
+    mdns_timeout: Option<u64>,
+
+    /// Timeout in milliseconds for LLMNR, overrides --timeout
+    #[arg(long, value_name = "TIMEOUT")]
+    llmnr_timeout: Option<u64>,
+
+    /// How many times unanswered request is sent again
+    #[arg(short, long, default_value_t = 0)]
+    retries: u32,
+
+    /// Delay in milliseconds before the first retry, doubled for every next one
+    #[arg(long, value_name = "MS", default_value_t = ScanConfig::DEFAULT_RETRY_BACKOFF_MS)]
+    retry_backoff: u64,
+
+    /// Maximum amount of hosts asked at the same time
+    #[arg(long, value_name = "HOSTS", default_value_t = ScanConfig::DEFAULT_MAX_IN_FLIGHT)]
+    max_in_flight: usize,
+
+    /// Maximum amount of packets sent per second
+    #[arg(long, value_name = "PACKETS")]
+    rate: Option<u32>,
+}
+impl Args {
+    /// Whether `result` passes `--workgroup` filter, results of other hosts are skipped with their errors.
+    fn shows(&self, result: &QueryResult) -> bool {
+        self.workgroup.as_ref().is_none_or(|workgroup| result.in_workgroup(workgroup))
+    }
+}
+
+fn warn_timeout(timeout: u64) {
+    match timeout {
+        0 ..= TOO_LOW_TIMEOUT_WARNING_MS => { eprintln!("The selected timeout may be too low for reciving answers")},
+        TOO_BIG_TIMEOUT_WARNING_MS..     => { eprintln!("The selected timeout may be too big and scanning may be slow")},
+        _ => {}
+    }
+}
+
+/// When the program is run with `--wait` flag, it doesn't output immediately and stores everything in
+/// `OutputBuffer`. JSON entries are always stored, because they are parts of one document, which
+/// has them in array under `json_key`, which is set by `begin`.
+struct OutputBuffer {
+    lines: Vec<String>,
+    json_key: Option<&'static str>,
+    /// Table mixes IPv4 and IPv6 addresses, so every row is padded for IPv6
+    wide_table: bool,
+}
+impl OutputBuffer {
+    fn new() -> Self {
+        Self { lines: Vec::new(), json_key: None, wide_table: false }
+    }
+    /// Writes header of table or CSV according to `--format`, and sets key of JSON array.
+    fn begin(&mut self, json_key: &'static str, table_head: String, csv_head: String, args: &Args) {
+        self.json_key = Some(json_key);
+        match args.format {
+            Format::Table if !(args.quiet || args.verbose) => self.write(table_head, args.wait),
+            Format::Csv | Format::Tsv => self.write(csv_head, args.wait),
+            _ => {},
+        }
+    }
+    fn write(&mut self, s: String, wait: bool) {
+        if wait {
+            self.lines.push(s);
+        } else {
+            println!("{}", s);
+        }
+    }
+    /// Formats `result` according to `--format` and writes it, if `Args::shows` it. Empty results
+    /// are skipped, but with NDJSON format their errors are written as separate records.
+    fn write_result(&mut self, result: &QueryResult, args: &Args) {
+        if !args.shows(result) { return };
+        if args.format == Format::Ndjson {
+            if let Some(error) = result.error_to_json() {
+                self.write(error, args.wait);
+            }
+        }
+        if result.is_empty() { return };
+
+        match args.format {
+            Format::Table if args.verbose => self.write(result.verbose_entry(), args.wait),
+            Format::Table if self.wide_table => self.write(result.table_row_wide(), args.wait),
+            Format::Table => self.write(result.table_row(), args.wait),
+            Format::Json => self.lines.push(result.to_json()),
+            Format::Ndjson => self.write(result.to_json(), args.wait),
+            Format::Csv | Format::Tsv => {
+                self.write(result.csv_row(&args.columns, args.format.separator()), args.wait);
+            },
+        }
+    }
+    /// Formats every address of name `lookup` according to `--format` and writes them.
+    fn write_lookup(&mut self, lookup: &NameLookup, args: &Args) {
+        match args.format {
+            Format::Table => lookup.table_rows().into_iter().for_each(|row| self.write(row, args.wait)),
+            Format::Json => self.lines.push(lookup.to_json()),
+            Format::Ndjson => self.write(lookup.to_json(), args.wait),
+            Format::Csv | Format::Tsv => {
+                lookup.csv_rows(args.format.separator()).into_iter().for_each(|row| self.write(row, args.wait));
+            },
+        }
+    }
+    /// Prints everything stored in `OutputBuffer`. Nothing is printed, if output wasn't begun.
+    fn flush(&self, args: &Args) {
+        let Some(json_key) = self.json_key else { return };
+        match args.format {
+            Format::Json => println!("{{\"{}\":[{}]}}", json_key, self.lines.join(",")),
+            _ if args.wait => {
+                for s in self.lines.iter() {
+                    println!("{}", s);
+                }
+            },
+            _ => {},
+        }
+    }
+}
+
+/// Main struct. Contains `Args`, `Resolver` and `OutputBuffer`.
+/// `ask` and `ask_multiple` will ask for hostnames and domain name, `lookup` will resolve NetBIOS
+/// name, `listen` will collect names from traffic, and output it to STDOUT or `OutputBuffer` when
+/// `--wait` option is set.
+/// On `drop` will print `OutputBuffer`, if should.
+struct App {
+    args: Args,
+    resolver: Resolver,
+    output_buffer: OutputBuffer,
+}
+impl App {
+    fn new(args: Args) -> Result<Self, AppError> {
+        let mut config = ScanConfig {
+            max_in_flight: Some(args.max_in_flight),
+            rate: args.rate,
+            retries: args.retries,
+            retry_backoff: Duration::from_millis(args.retry_backoff),
+            mdns_multicast: args.mdns_multicast,
+            protocols: args.protocols.clone(),
+            oui_file: args.oui_file.clone(),
+            ..ScanConfig::default()
+        };
+        let nbns_timeout = args.nbns_timeout.or(args.timeout);
+        let mdns_timeout = args.mdns_timeout.or(args.timeout);
+        let llmnr_timeout = args.llmnr_timeout.or(args.timeout);
+        if let Some(new_timeout) = nbns_timeout {
+            warn_timeout(new_timeout);
+            config.nbns_timeout = Duration::from_millis(new_timeout);
+        }
+        if let Some(new_timeout) = mdns_timeout {
+            // same timeout shouldn't be warned about twice
+            if mdns_timeout != nbns_timeout { warn_timeout(new_timeout) };
+            config.mdns_timeout = Duration::from_millis(new_timeout);
+        }
+        if let Some(new_timeout) = llmnr_timeout {
+            if llmnr_timeout != nbns_timeout && llmnr_timeout != mdns_timeout { warn_timeout(new_timeout) };
+            config.llmnr_timeout = Duration::from_millis(new_timeout);
+        }
+
+        Ok(App {
+                resolver: Resolver::new(config)?,
+                output_buffer: OutputBuffer::new(),
+                args,
+            })
+    }
+
+    /// Asks `addr` for any names and outputs them to STDOUT or to `OutputBuffer` when `--wait` is set.
+    /// `scope_id` is used only for IPv6 addresses, if it's not zero.
+    /// When querying resulted an error, will return `AppError`, unless the host is filtered out by `--workgroup`.
+    fn ask(&mut self, addr: std::net::IpAddr, scope_id: u32) -> Result<(), AppError> {
+        let result = match addr {
+            std::net::IpAddr::V6(a) if scope_id != 0 => self.resolver.resolve_scoped(a, scope_id)?,
+            _ => self.resolver.resolve(addr)?,
+        };
+        self.output_buffer.write_result(&result, &self.args);
+
+        match result.error() {
+            Some(e) if self.args.shows(&result) => Err(e),
+            _ => Ok(()),
+        }
+    }
+    /// Asks every host in `addr_range` and outputs results to STDOUT or `OutputBuffer` when `--wait` is set.
+    /// When any of querying resulted an error, will print address and error to STDERR and return `AppError::ScanError`.
+    /// Errors of hosts filtered out by `--workgroup` are ignored.
+    /// IPv6 hosts are discovered on network interface with `scope_id` index, or on any, if it's zero.
+    /// With `--broadcast` IPv4 hosts are discovered by broadcast NetBIOS request instead.
+    fn ask_multiple(&mut self, addr_range: ipnet::IpNet, scope_id: u32) -> Result<(), AppError> {
+        let mut errors = Vec::new();
+
+        let App { args, resolver, output_buffer } = self;
+        let on_result = |result: askhostname::QueryResult| {
+            output_buffer.write_result(&result, args);
+            if let Some(e) = result.error().filter(|_| args.shows(&result)) {
+                errors.push((result.ip_addr(), e));
+            }
+        };
+        match addr_range {
+            ipnet::IpNet::V4(range) if args.broadcast => resolver.broadcast_with(range, on_result)?,
+            ipnet::IpNet::V6(range) if scope_id != 0 => resolver.scan_scoped_with(range, scope_id, on_result)?,
+            _ => resolver.scan_with(addr_range, on_result)?,
+        }
+
+        if !errors.is_empty() {
+            for (addr, err) in errors.iter() {
+                eprintln!("Error for {}: {}", addr, err);
+            }
+            return Err(AppError::ScanError);
+        }
+
+        Ok(())
+    }
+    /// Resolves `name` by NetBIOS broadcast or `--wins` server and by LLMNR, then outputs owners' addresses.
+    /// If nobody answered, will return `AppError::NameNotFound`.
+    fn lookup(&mut self, name: &NetbiosName) -> Result<(), AppError> {
+        let lookup = self.resolver.lookup_name(name, self.args.wins)?;
+        if lookup.is_empty() { return Err(AppError::NameNotFound) };
+
+        let separator = self.args.format.separator();
+        self.output_buffer.begin("names", NameLookup::table_head(), NameLookup::csv_head(separator), &self.args);
+        self.output_buffer.write_lookup(&lookup, &self.args);
+        Ok(())
+    }
+    /// Listens to traffic of other hosts for `duration`, or until Ctrl+C, and outputs them as soon as they
+    /// are heard, again with every new name. With `--wait` or JSON output only the last result of
+    /// every host is written, after listening has stopped.
+    /// Nothing is written, if ports can't be bound.
+    fn listen(&mut self, duration: Option<Duration>) -> Result<(), AppError> {
+        let App { args, resolver, output_buffer } = self;
+        let collect = args.wait || args.format == Format::Json;
+        let listening = resolver.listen()?;
+
+        // mDNS announces IPv6 addresses too
+        let head_addr = std::net::IpAddr::V6(std::net::Ipv6Addr::UNSPECIFIED);
+        output_buffer.wide_table = true;
+        output_buffer.begin("hosts", QueryResult::table_head(&head_addr), QueryResult::csv_head(&args.columns, args.format.separator()), args);
+
+        let mut heard: Vec<QueryResult> = Vec::new();
+        listening.run_with(duration, |result| {
+            if !collect {
+                output_buffer.write_result(&result, args);
+                return;
+            }
+            match heard.iter_mut().find(|r| r.ip_addr() == result.ip_addr()) {
+                Some(known) => *known = result,
+                None => heard.push(result),
+            }
+        })?;
+
+        for result in heard.iter() {
+            output_buffer.write_result(result, args);
+        }
+        Ok(())
+    }
+}
+impl Drop for App {
+    fn drop(&mut self) {
+        self.output_buffer.flush(&self.args);
+    }
+}
+
+fn run(args: Args) -> Result<(), AppError> {
+    let separator = args.format.separator();
+    if let Some(Command::Listen { duration }) = args.command {
+        let mut app = App::new(args)?;
+        return app.listen(duration.map(Duration::from_secs));
+    }
+
+    // clap requires target without subcommand
+    let target_arg = args.target.clone().unwrap_or_default();
+    let target: Target = match target_arg.parse() {
+        Ok(target) => target,
+        // anything, that doesn't look like address, may be NetBIOS name
+        Err(AppError::ParseAddress) => {
+            let name: NetbiosName = target_arg.parse().map_err(|_| AppError::ParseAddress)?;
+            let mut app = App::new(args)?;
+            return app.lookup(&name);
+        },
+        Err(e) => return Err(e),
+    };
+    if args.broadcast && !matches!(target, Target::Range(ipnet::IpNet::V4(_))) {
+        return Err(AppError::BroadcastRange);
+    }
+    if args.broadcast && !args.protocols.contains(&Protocol::Nbns) {
+        return Err(AppError::BroadcastProtocol);
+    }
+    let mut app = App::new(args)?;
+    let csv_head = QueryResult::csv_head(&app.args.columns, separator);
+    app.output_buffer.begin("hosts", QueryResult::table_head(&target.addr()), csv_head, &app.args);
+
+    match target {
+        Target::Host(addr) => app.ask(addr, 0),
+        Target::ScopedHost(addr, scope_id) => app.ask(addr.into(), scope_id),
+        Target::Range(range) => app.ask_multiple(range, 0),
+        Target::ScopedRange(range, scope_id) => app.ask_multiple(range.into(), scope_id),
+    }
+}
 
 fn main() -> std::process::ExitCode {
 
@@ -0,0 +1,327 @@
+// DNS message decoding and name encoding, shared by mDNS, LLMNR and NetBIOS name service
+// https://www.rfc-editor.org/rfc/rfc1035#section-4
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+pub const TYPE_A: u16 = 0x0001;
+pub const TYPE_PTR: u16 = 0x000c;
+pub const TYPE_AAAA: u16 = 0x001c;
+
+/// Encodes domain name as labels preceded by their size and ended with 0x00,
+/// for example "abc.com" is 0x03 0x61 0x62 0x63 0x03 0x63 0x6f 0x6d 0x00.
+/// Labels longer than 63 bytes are truncated.
+pub fn encode_name(name: &str) -> Vec<u8> {
+    let mut encoded = Vec::with_capacity(name.len() + 2);
+    for label in name.split('.').filter(|l| !l.is_empty()) {
+        let label = &label.as_bytes()[..label.len().min(63)];
+        encoded.push(label.len() as u8);
+        encoded.extend_from_slice(label);
+    }
+    encoded.push(0);
+    encoded
+}
+
+/// Name for reverse lookup of `ip`: reversed octets + .in-addr.arpa for IPv4, and reversed
+/// nibbles + .ip6.arpa for IPv6, so ::1 is 1.0.0.0. ... .0.ip6.arpa
+pub fn reverse_name(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(a) => {
+            let [a, b, c, d] = a.octets();
+            format!("{}.{}.{}.{}.in-addr.arpa", d, c, b, a)
+        },
+        IpAddr::V6(a) => {
+            let mut name = String::with_capacity(72);
+            for octet in a.octets().iter().rev() {
+                name.push_str(&format!("{:x}.{:x}.", octet & 0x0f, octet >> 4));
+            }
+            name.push_str("ip6.arpa");
+            name
+        },
+    }
+}
+
+/// Address from name for reverse lookup, made by `reverse_name`. Returns `None` for other names.
+pub fn parse_reverse_name(name: &str) -> Option<IpAddr> {
+    let lower = name.to_ascii_lowercase();
+    if let Some(octets) = lower.strip_suffix(".in-addr.arpa") {
+        let octets: Vec<u8> = octets.split('.').rev().map(|o| o.parse().ok()).collect::<Option<_>>()?;
+        let octets: [u8; 4] = octets.try_into().ok()?;
+        Some(IpAddr::V4(Ipv4Addr::from(octets)))
+    } else if let Some(nibbles) = lower.strip_suffix(".ip6.arpa") {
+        let nibbles: Vec<u8> = nibbles.split('.').rev()
+            .map(|n| u8::from_str_radix(n, 16).ok().filter(|_| n.len() == 1))
+            .collect::<Option<_>>()?;
+        if nibbles.len() != 32 { return None };
+        let mut octets = [0u8; 16];
+        for (i, pair) in nibbles.chunks(2).enumerate() {
+            octets[i] = (pair[0] << 4) | pair[1];
+        }
+        Some(IpAddr::V6(Ipv6Addr::from(octets)))
+    } else {
+        None
+    }
+}
+
+/// Decoded DNS message with every section.
+pub struct Message {
+    pub id: u16,
+    pub flags: u16,
+    pub questions: Vec<Question>,
+    pub answers: Vec<Record>,
+    pub authority: Vec<Record>,
+    pub additional: Vec<Record>,
+}
+
+pub struct Question {
+    pub name: String,
+    pub qtype: u16,
+    pub qclass: u16,
+}
+
+pub struct Record {
+    pub name: String,
+    pub rtype: u16,
+    pub class: u16,
+    pub ttl: u32,
+    pub data: RecordData,
+}
+
+pub enum RecordData {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    Ptr(String),
+    /// Raw data of any other record type
+    Other(Vec<u8>),
+}
+
+impl Message {
+    const HEADER_SIZE: usize = 12;
+
+    /// Decodes message from `buff`. Returns `None` if message is malformed or truncated.
+    pub fn parse(buff: &[u8]) -> Option<Self> {
+        if buff.len() < Self::HEADER_SIZE { return None };
+
+        let mut reader = Reader { buff, pos: 0 };
+        let id = reader.u16()?;
+        let flags = reader.u16()?;
+        let qdcount = reader.u16()?;
+        let ancount = reader.u16()?;
+        let nscount = reader.u16()?;
+        let arcount = reader.u16()?;
+
+        let mut questions = Vec::new();
+        for _ in 0..qdcount {
+            questions.push(Question {
+                name: reader.name()?,
+                qtype: reader.u16()?,
+                qclass: reader.u16()?,
+            });
+        }
+
+        Some(Message {
+            id,
+            flags,
+            questions,
+            answers: reader.records(ancount)?,
+            authority: reader.records(nscount)?,
+            additional: reader.records(arcount)?,
+        })
+    }
+    /// QR bit of flags is set in responses.
+    pub fn is_response(&self) -> bool {
+        self.flags & 0x8000 != 0
+    }
+    /// Every record of answer, authority and additional sections.
+    pub fn records(&self) -> impl Iterator<Item = &Record> {
+        self.answers.iter().chain(self.authority.iter()).chain(self.additional.iter())
+    }
+}
+
+/// Reads big-endian values from message, keeping current position.
+struct Reader<'a> {
+    buff: &'a [u8],
+    pos: usize,
+}
+impl<'a> Reader<'a> {
+    /// Names longer than that are invalid
+    const MAX_NAME_SIZE: usize = 255;
+
+    fn bytes(&mut self, size: usize) -> Option<&'a [u8]> {
+        let bytes = self.buff.get(self.pos..self.pos.checked_add(size)?)?;
+        self.pos += size;
+        Some(bytes)
+    }
+    fn u16(&mut self) -> Option<u16> {
+        let b = self.bytes(2)?;
+        Some(u16::from_be_bytes([b[0], b[1]]))
+    }
+    fn u32(&mut self) -> Option<u32> {
+        let b = self.bytes(4)?;
+        Some(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    /// Reads domain name, following compression pointers.
+    /// Name is encoded as labels preceded by their size and ended with 0x00, two highest bits of
+    /// the size set means pointer to the rest of the name at the offset in 14 lower bits.
+    /// Pointers may only point backward, but a label followed by pointer to itself still makes a
+    /// loop, which ends when the name exceeds 255 bytes of `MAX_NAME_SIZE`.
+    fn name(&mut self) -> Option<String> {
+        let mut labels: Vec<String> = Vec::new();
+        let mut size = 0;
+        // position is restored after the first pointer
+        let mut pos = self.pos;
+        let mut end = None;
+
+        loop {
+            let len = *self.buff.get(pos)?;
+            match len & 0xc0 {
+                0x00 if len == 0 => {
+                    pos += 1;
+                    break;
+                },
+                0x00 => {
+                    let label = self.buff.get(pos + 1..pos + 1 + len as usize)?;
+                    size += label.len() + 1;
+                    if size > Self::MAX_NAME_SIZE { return None };
+
+                    labels.push(String::from_utf8_lossy(label).into_owned());
+                    pos += 1 + len as usize;
+                },
+                0xc0 => {
+                    let target = (((len & 0x3f) as usize) << 8) | *self.buff.get(pos + 1)? as usize;
+                    if target >= pos { return None };
+
+                    end.get_or_insert(pos + 2);
+                    pos = target;
+                },
+                _ => return None, // extended label types are obsolete
+            }
+        }
+
+        self.pos = end.unwrap_or(pos);
+        Some(labels.join("."))
+    }
+
+    fn records(&mut self, count: u16) -> Option<Vec<Record>> {
+        let mut records = Vec::new();
+        for _ in 0..count {
+            let name = self.name()?;
+            let rtype = self.u16()?;
+            let class = self.u16()?;
+            let ttl = self.u32()?;
+            let rdlength = self.u16()? as usize;
+
+            let data_start = self.pos;
+            let raw = self.bytes(rdlength)?;
+            let data = match rtype {
+                TYPE_A => RecordData::A(Ipv4Addr::from(<[u8; 4]>::try_from(raw).ok()?)),
+                TYPE_AAAA => RecordData::Aaaa(Ipv6Addr::from(<[u8; 16]>::try_from(raw).ok()?)),
+                TYPE_PTR => {
+                    // name in data may be compressed too
+                    let mut data_reader = Reader { buff: self.buff, pos: data_start };
+                    RecordData::Ptr(data_reader.name()?)
+                },
+                _ => RecordData::Other(raw.to_vec()),
+            };
+
+            records.push(Record { name, rtype, class, ttl, data });
+        }
+        Some(records)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Header of response with `qdcount` questions and `ancount` answers.
+    fn header(qdcount: u16, ancount: u16) -> Vec<u8> {
+        let mut packet = vec![0x12, 0x34, 0x84, 0x00];
+        for count in [qdcount, ancount, 0, 0] {
+            packet.extend_from_slice(&count.to_be_bytes());
+        }
+        packet
+    }
+
+    #[test]
+    fn compressed_answer_name() {
+        let mut packet = header(1, 1);
+        packet.extend_from_slice(b"\x04host\x05local\x00\x00\x01\x00\x01");
+        // name is pointer to the question at offset 12
+        packet.extend_from_slice(&[0xc0, 0x0c, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x78, 0x00, 0x04, 192, 168, 1, 10]);
+
+        let message = Message::parse(&packet).unwrap();
+        assert_eq!(message.id, 0x1234);
+        assert!(message.is_response());
+        assert_eq!(message.questions[0].name, "host.local");
+        assert_eq!(message.answers[0].name, "host.local");
+        assert_eq!(message.answers[0].ttl, 120);
+        assert!(matches!(message.answers[0].data, RecordData::A(a) if a == Ipv4Addr::new(192, 168, 1, 10)));
+    }
+
+    #[test]
+    fn ptr_data_with_pointer() {
+        let mut packet = header(1, 1);
+        // question "10.1.168.192.in-addr.arpa"
+        packet.extend_from_slice(b"\x0210\x011\x03168\x03192\x07in-addr\x04arpa\x00\x00\x0c\x00\x01");
+        packet.extend_from_slice(&[0xc0, 0x0c, 0x00, 0x0c, 0x00, 0x01, 0x00, 0x00, 0x00, 0x78, 0x00, 0x07]);
+        // "host" followed by pointer to "arpa" label of the question, which is at offset 33
+        packet.extend_from_slice(b"\x04host\xc0\x21");
+
+        let message = Message::parse(&packet).unwrap();
+        assert_eq!(message.answers[0].name, "10.1.168.192.in-addr.arpa");
+        assert!(matches!(&message.answers[0].data, RecordData::Ptr(name) if name == "host.arpa"));
+    }
+
+    #[test]
+    fn self_pointer_is_rejected() {
+        let mut packet = header(1, 0);
+        packet.extend_from_slice(&[0xc0, 0x0c, 0x00, 0x01, 0x00, 0x01]);
+        assert!(Message::parse(&packet).is_none());
+    }
+
+    #[test]
+    fn backward_pointer_loop_is_rejected() {
+        let mut packet = header(1, 0);
+        // label "a" at offset 12 followed by pointer back to it
+        packet.extend_from_slice(&[0x01, b'a', 0xc0, 0x0c, 0x00, 0x01, 0x00, 0x01]);
+        assert!(Message::parse(&packet).is_none());
+    }
+
+    #[test]
+    fn forward_pointer_is_rejected() {
+        let mut packet = header(1, 0);
+        // pointer to the label after the question
+        packet.extend_from_slice(&[0xc0, 0x12, 0x00, 0x01, 0x00, 0x01]);
+        packet.extend_from_slice(b"\x04host\x00");
+        assert!(Message::parse(&packet).is_none());
+    }
+
+    #[test]
+    fn truncated_rdlength_is_rejected() {
+        let mut packet = header(0, 1);
+        packet.extend_from_slice(b"\x04host\x00\x00\x01\x00\x01\x00\x00\x00\x78\x00\x04");
+        packet.extend_from_slice(&[192, 168]);
+        assert!(Message::parse(&packet).is_none());
+    }
+
+    #[test]
+    fn reverse_name_v4() {
+        let addr: IpAddr = "192.168.1.10".parse().unwrap();
+        assert_eq!(reverse_name(addr), "10.1.168.192.in-addr.arpa");
+        assert_eq!(parse_reverse_name(&reverse_name(addr)), Some(addr));
+        assert_eq!(parse_reverse_name("10.1.168.192.IN-ADDR.ARPA"), Some(addr));
+        assert_eq!(parse_reverse_name("1.168.192.in-addr.arpa"), None);
+    }
+
+    #[test]
+    fn reverse_name_v6() {
+        let addr: IpAddr = "fe80::1:abcd".parse().unwrap();
+        let name = reverse_name(addr);
+        assert!(name.starts_with("d.c.b.a.1.0.0.0."));
+        assert!(name.ends_with(".0.8.e.f.ip6.arpa"));
+        assert_eq!(parse_reverse_name(&name), Some(addr));
+        assert_eq!(parse_reverse_name("1.0.ip6.arpa"), None);
+        assert_eq!(parse_reverse_name("host.local"), None);
+    }
+}
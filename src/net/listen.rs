@@ -0,0 +1,203 @@
+// Passive listening of mDNS and NetBIOS name service traffic, nothing is ever sent
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4};
+use tokio::net::UdpSocket;
+use crate::AppError;
+use crate::net::{RECV_BUFF_SIZE, mdns::MdnsQuery, nbns::{NbAddress, NbnsAnswer, NbnsQuery, NetbiosName, NodeStatus}};
+use crate::net::dns::{Message, RecordData, parse_reverse_name};
+
+/// Something learned about the host with address from traffic.
+pub enum Heard {
+    /// NetBIOS name from registration, refresh or name query response
+    NbnsName(IpAddr, NbnsAnswer),
+    /// Node status response
+    NodeStatus(IpAddr, NodeStatus),
+    /// Name from mDNS A, AAAA or reverse PTR record
+    DomainName(IpAddr, String),
+}
+
+/// Sockets bound to mDNS and NBNS ports, which receive traffic of other hosts.
+pub struct Listener {
+    mdns: Option<UdpSocket>,
+    nbns: Option<UdpSocket>,
+}
+impl Listener {
+    /// Joins mDNS multicast group on 5353 port, if `mdns` is set, and binds NBNS 137 port for
+    /// broadcasts, if `nbns` is set. Ports are shared with other programs, like Avahi or Samba.
+    /// Returns `AppError::ListenBind` if any of ports can't be bound, NBNS port is usually
+    /// available only for root. Must be called inside of tokio runtime.
+    pub fn bind(mdns: bool, nbns: bool) -> Result<Self, AppError> {
+        let mdns = match mdns {
+            true => {
+                let sock = Self::bind_shared(MdnsQuery::PORT)?;
+                sock.join_multicast_v4(MdnsQuery::MULTICAST_V4, Ipv4Addr::UNSPECIFIED).map_err(|_| AppError::ListenBind)?;
+                Some(sock)
+            },
+            false => None,
+        };
+        let nbns = match nbns {
+            true => {
+                let sock = Self::bind_shared(NbnsQuery::PORT)?;
+                sock.set_broadcast(true).map_err(|_| AppError::ListenBind)?;
+                Some(sock)
+            },
+            false => None,
+        };
+
+        Ok(Listener { mdns, nbns })
+    }
+    /// Binds UDP socket to `port` of every IPv4 address with SO_REUSEADDR.
+    fn bind_shared(port: u16) -> Result<UdpSocket, AppError> {
+        let sock = socket2::Socket::new(socket2::Domain::IPV4, socket2::Type::DGRAM, Some(socket2::Protocol::UDP))
+            .map_err(|_| AppError::SocketCreate)?;
+        sock.set_reuse_address(true).map_err(|_| AppError::ListenBind)?;
+        sock.set_nonblocking(true).map_err(|_| AppError::ListenBind)?;
+        sock.bind(&SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, port).into()).map_err(|_| AppError::ListenBind)?;
+
+        UdpSocket::from_std(sock.into()).map_err(|_| AppError::ListenBind)
+    }
+
+    /// Receives datagrams until the future is dropped and calls `on_heard` for everything learned
+    /// from them. Malformed datagrams are ignored.
+    /// Returns `AppError::ListenReceive` if any of sockets fails to receive.
+    pub async fn run<F>(&self, mut on_heard: F) -> Result<(), AppError>
+    where F: FnMut(Heard)
+    {
+        let mut mdns_buff = [0u8; RECV_BUFF_SIZE];
+        let mut nbns_buff = [0u8; RECV_BUFF_SIZE];
+        loop {
+            tokio::select! {
+                Some(received) = Self::recv(&self.mdns, &mut mdns_buff) => {
+                    let Some((size, _)) = received? else { continue };
+                    Self::parse_mdns(&mdns_buff[..size]).into_iter().for_each(&mut on_heard);
+                },
+                Some(received) = Self::recv(&self.nbns, &mut nbns_buff) => {
+                    let Some((size, from)) = received? else { continue };
+                    Self::parse_nbns(&nbns_buff[..size], from.ip()).into_iter().for_each(&mut on_heard);
+                },
+                else => return Ok(()),
+            }
+        }
+    }
+    /// Receives from `sock`, or returns `None` if it's not bound. ICMP errors caused by other
+    /// programs sharing the port are received as `Ok(None)`.
+    async fn recv(sock: &Option<UdpSocket>, buff: &mut [u8]) -> Option<Result<Option<(usize, SocketAddr)>, AppError>> {
+        let sock = sock.as_ref()?;
+        Some(match sock.recv_from(buff).await {
+            Ok(received) => Ok(Some(received)),
+            Err(e) if matches!(e.kind(), std::io::ErrorKind::ConnectionRefused | std::io::ErrorKind::ConnectionReset) => Ok(None),
+            Err(_) => Err(AppError::ListenReceive),
+        })
+    }
+
+    /// Every mDNS query and response may have records, like known answers of queries and proposed
+    /// records of probes, which map names to addresses.
+    fn parse_mdns(packet: &[u8]) -> Vec<Heard> {
+        let Some(message) = Message::parse(packet) else { return Vec::new() };
+
+        message.records()
+            .filter_map(|r| match &r.data {
+                RecordData::A(a) => Some(Heard::DomainName(IpAddr::V4(*a), r.name.clone())),
+                RecordData::Aaaa(a) => Some(Heard::DomainName(IpAddr::V6(*a), r.name.clone())),
+                RecordData::Ptr(name) => Some(Heard::DomainName(parse_reverse_name(&r.name)?, name.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+    /// NB records with addresses are in name registrations, refreshes and name query responses,
+    /// name releases are skipped. Node status response describes its sender.
+    fn parse_nbns(packet: &[u8], from: IpAddr) -> Vec<Heard> {
+        const OPCODE_RELEASE: u16 = 6;
+
+        let Some(message) = Message::parse(packet) else { return Vec::new() };
+        let opcode = (message.flags >> 11) & 0x0f;
+        let rcode = message.flags & 0x000f;
+        if opcode == OPCODE_RELEASE || rcode != 0 { return Vec::new() };
+
+        let mut heard = Vec::new();
+        if let Some(status) = NodeStatus::from_response(packet) {
+            heard.push(Heard::NodeStatus(from, status));
+        }
+        for record in message.records().filter(|r| r.rtype == NbnsQuery::NB) {
+            let RecordData::Other(data) = &record.data else { continue };
+            let Some(name) = NetbiosName::decode(&record.name) else { continue };
+            for address in NbAddress::parse(data).unwrap_or_default() {
+                let name = (name.name.clone(), name.suffix);
+                heard.push(Heard::NbnsName(
+                    IpAddr::V4(address.addr),
+                    if address.group { NbnsAnswer::Group(name) } else { NbnsAnswer::Unique(name) },
+                ));
+            }
+        }
+        heard
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv6Addr;
+
+    /// NBNS packet with `flags` and additional NB record of WORKSTN1<00> for 192.0.2.8, as in name
+    /// registration and release.
+    fn nbns_packet(flags: u16, nb_flags: u16) -> Vec<u8> {
+        let mut packet = vec![0x12, 0x34];
+        packet.extend_from_slice(&flags.to_be_bytes());
+        packet.extend_from_slice(b"\x00\x01\x00\x00\x00\x00\x00\x01\x20");
+        for b in b"WORKSTN1       \x00" {
+            packet.extend_from_slice(&[b'A' + (b >> 4), b'A' + (b & 0x0f)]);
+        }
+        packet.extend_from_slice(b"\x00\x00\x20\x00\x01");
+        packet.extend_from_slice(b"\xc0\x0c\x00\x20\x00\x01\x00\x04\x93\xe0\x00\x06");
+        packet.extend_from_slice(&nb_flags.to_be_bytes());
+        packet.extend_from_slice(&[192, 0, 2, 8]);
+        packet
+    }
+
+    #[test]
+    fn nbns_registration() {
+        let from = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 8));
+        let name = ("WORKSTN1".to_string(), 0x00);
+
+        let heard = Listener::parse_nbns(&nbns_packet(0x2910, 0x0000), from);
+        assert_eq!(heard.len(), 1);
+        assert!(matches!(&heard[0], Heard::NbnsName(addr, NbnsAnswer::Unique(n)) if *addr == from && *n == name));
+
+        let heard = Listener::parse_nbns(&nbns_packet(0x2910, 0x8000), from);
+        assert_eq!(heard.len(), 1);
+        assert!(matches!(&heard[0], Heard::NbnsName(addr, NbnsAnswer::Group(n)) if *addr == from && *n == name));
+    }
+
+    #[test]
+    fn nbns_release_and_negative_response_are_skipped() {
+        let from = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 8));
+        assert!(Listener::parse_nbns(&nbns_packet(0x3010, 0x0000), from).is_empty());
+        // negative name registration response with ACT_ERR rcode
+        assert!(Listener::parse_nbns(&nbns_packet(0xad86, 0x0000), from).is_empty());
+    }
+
+    #[test]
+    fn mdns_announcement() {
+        let mut packet = b"\x00\x00\x84\x00\x00\x00\x00\x04\x00\x00\x00\x00".to_vec();
+        packet.extend_from_slice(b"\x07printer\x05local\x00\x00\x01\x80\x01\x00\x00\x00\x78\x00\x04\xc0\x00\x02\x07");
+        packet.extend_from_slice(b"\xc0\x0c\x00\x1c\x80\x01\x00\x00\x00\x78\x00\x10");
+        packet.extend_from_slice(&Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 7).octets());
+        packet.extend_from_slice(b"\x017\x012\x010\x03192\x07in-addr\x04arpa\x00\x00\x0c\x80\x01\x00\x00\x00\x78\x00\x02\xc0\x0c");
+        // service PTR isn't reverse name, so it doesn't map any address
+        packet.extend_from_slice(b"\x05_http\x04_tcp\xc0\x14\x00\x0c\x00\x01\x00\x00\x00\x78\x00\x02\xc0\x0c");
+
+        let heard = Listener::parse_mdns(&packet);
+        let names: Vec<(IpAddr, &str)> = heard.iter()
+            .filter_map(|h| match h {
+                Heard::DomainName(addr, name) => Some((*addr, name.as_str())),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(heard.len(), 3);
+        assert_eq!(names, [
+            (IpAddr::V4(Ipv4Addr::new(192, 0, 2, 7)), "printer.local"),
+            (IpAddr::V6(Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 7)), "printer.local"),
+            (IpAddr::V4(Ipv4Addr::new(192, 0, 2, 7)), "printer.local"),
+        ]);
+    }
+}
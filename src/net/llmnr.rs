@@ -0,0 +1,109 @@
+// Link-Local Multicast Name Resolution
+// https://www.rfc-editor.org/rfc/rfc4795.html
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6};
+use tokio::net::UdpSocket;
+use crate::AppError;
+use crate::net::{DnsHeader, DnsQuestion, Receiving, Session, query, query_each};
+use crate::net::dns::{Message, RecordData, TYPE_A, TYPE_AAAA, TYPE_PTR, encode_name, reverse_name};
+
+pub struct LlmnrQuery {
+    header: DnsHeader,
+    question: DnsQuestion,
+}
+// Reverse queries are sent by unicast directly to the host, forward queries are multicast
+impl LlmnrQuery {
+    pub const PORT: u16 = 5355;
+    pub const MULTICAST_V4: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 252);
+    pub const MULTICAST_V6: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0x0001, 0x0003);
+
+    fn new(name: &str, qtype: u16) -> Self {
+        LlmnrQuery {
+            header: DnsHeader::new_llmnr(),
+            question: DnsQuestion {
+                qname: encode_name(name),
+                qtype,
+                qclass: 0x0001 // IN
+            },
+        }
+    }
+    fn to_packet(&self) -> Vec<u8> {
+        self.header.to_packet(&self.question)
+    }
+    /// Decodes `response` to this query. Returns `None` if it's not response with the same
+    /// transaction ID, and empty message if responder doesn't know the name (RCODE isn't zero).
+    fn parse_response(&self, response: &[u8]) -> Option<Message> {
+        let mut message = Message::parse(response)?;
+        if !message.is_response() || message.id != self.header.trans_id { return None };
+
+        if message.flags & 0x000f != 0 {
+            message.answers.clear();
+        }
+        Some(message)
+    }
+
+    /// Asks `addr` for its name by PTR query, RFC 4795 2.4 recommends unicast for them.
+    /// `scope_id` is the interface index for link-local IPv6 addresses, it's ignored for IPv4.
+    /// Returns the name with number of sent requests.
+    pub async fn send(addr: IpAddr, scope_id: u32, session: &Session) -> Result<Option<(String, u32)>, AppError> {
+        let name = reverse_name(addr);
+        let request = Self::new(&name, TYPE_PTR);
+
+        let remote: SocketAddr = match addr {
+            IpAddr::V4(a) => (a, Self::PORT).into(),
+            IpAddr::V6(a) => SocketAddrV6::new(a, Self::PORT, 0, scope_id).into(),
+        };
+        let answer = query(remote, &request.to_packet(), session.config.llmnr_timeout, session).await?;
+        let Some((buff, attempts)) = answer else { return Ok(None) };
+        let response = request.parse_response(&buff).ok_or(AppError::InvalidResponseLlmnr)?;
+
+        let hostname = response.answers.iter()
+            .filter(|r| r.name.eq_ignore_ascii_case(&name))
+            .find_map(|r| match &r.data {
+                RecordData::Ptr(hostname) => Some(hostname.clone()),
+                _ => None,
+            });
+
+        Ok(hostname.map(|h| (h, attempts)))
+    }
+
+    /// Finds addresses of `name` by A query to IPv4 multicast group and AAAA query to IPv6 one,
+    /// collecting answers of every host received in time. IPv6 query is sent through the default
+    /// interface and is skipped, if there is no IPv6 connectivity.
+    pub async fn resolve_name(name: &str, session: &Session) -> Result<Vec<IpAddr>, AppError> {
+        let mut addresses = Self::resolve_with(name, TYPE_A, "0.0.0.0:0", (Self::MULTICAST_V4, Self::PORT).into(), session).await?;
+        if let Ok(v6) = Self::resolve_with(name, TYPE_AAAA, "[::]:0", (Self::MULTICAST_V6, Self::PORT).into(), session).await {
+            for addr in v6 {
+                if !addresses.contains(&addr) { addresses.push(addr) };
+            }
+        }
+        Ok(addresses)
+    }
+    async fn resolve_with(name: &str, qtype: u16, local_addr: &str, group: SocketAddr, session: &Session) -> Result<Vec<IpAddr>, AppError> {
+        let query = Self::new(name, qtype);
+        let request = query.to_packet();
+        let sock = UdpSocket::bind(local_addr).await.map_err(|_| AppError::SocketCreate)?;
+
+        let mut addresses = Vec::new();
+        query_each(&sock, group, &request, session.config.llmnr_timeout, session, |datagram, _, _| {
+            let Some(response) = query.parse_response(datagram) else { return Receiving::Continue };
+
+            let found: Vec<IpAddr> = response.answers.iter()
+                .filter(|r| r.name.eq_ignore_ascii_case(name.trim_end_matches('.')))
+                .filter_map(|r| match r.data {
+                    RecordData::A(a) => Some(IpAddr::V4(a)),
+                    RecordData::Aaaa(a) => Some(IpAddr::V6(a)),
+                    _ => None,
+                })
+                .collect();
+            if found.is_empty() { return Receiving::Continue };
+
+            for addr in found {
+                if !addresses.contains(&addr) { addresses.push(addr) };
+            }
+            Receiving::Answered
+        }).await?;
+
+        Ok(addresses)
+    }
+}
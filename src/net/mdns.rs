@@ -1,118 +1,152 @@
 // Multicast DNS
 // https://www.rfc-editor.org/rfc/rfc6762.html
 
-use std::net::IpAddr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6};
+use tokio::net::UdpSocket;
 use crate::AppError;
-use crate::net::{DnsHeader, query};
+use crate::net::{DnsHeader, DnsQuestion, Receiving, Session, query, query_each};
+use crate::net::dns::{Message, RecordData, TYPE_PTR, encode_name, reverse_name};
 
-#[repr(C)]
 pub struct MdnsQuery {
     header: DnsHeader,
-
-    qname: Vec<u8>, // is dynamic size
-    qtype: u16,
-    qclass: u16 // first bit is UNICAST-RESPONSE flag for QU (query unicast), which desires
-                // unicast respose back to the host
+    // first bit of qclass is UNICAST-RESPONSE flag for QU (query unicast), which desires unicast
+    // respose back to the host
+    question: DnsQuestion,
 }
-// Unicast direct reverse DNS lookup query with unicast response directly to the host
+// Reverse DNS lookup query sent directly to the host, or to multicast group with unicast response
+// requested
 impl MdnsQuery {
     pub const PORT: u16 = 5353;
-    const MIN_RESPONSE_SIZE: usize = 18;
+    pub const MULTICAST_V4: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+    pub const MULTICAST_V6: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0xfb);
 
     fn new(ip: IpAddr) -> Self {
-        let mut question = vec![];
-
-        // name is encoded as ASCII octets preceded by their amount, ended with NULL-terminator (0x00)
-        // for example domain "abc.com" would be 0x03 0x41 0x42 0x43 0x03 0x43 0x6f 0x6d 0x00
-        // for example addres 127.0.0.1 is 0x03 0x31 0x32 0x37 0x01 0x30 0x01 0x30 0x01 0x31 0x00
-        // note that there is no '.' (0x2e), instead amount of octets
-        // for reverse DNS lookup address is reversed and represented like char arrays + .in-addr.arpa
-        match ip {
-            IpAddr::V4(a) => {
-                let octets: Vec<String> = a.octets().into_iter().map(|x| x.to_string()).collect();
-                for octet in octets.iter().rev() {
-                    question.push(octet.len() as u8);
-                    octet.chars().for_each(|c| { question.push(c as u8); });
-                }
-            },
-            IpAddr::V6(_a) => {
-                todo!("IPv6 is not implemented yet")
-            }
-        };
-
-        question.push(7); // size of "in-addr"
-        "in-addr".chars().for_each(|c| question.push(c as u8));
-        question.push(4); // size of "arpa"
-        "arpa".chars().for_each(|c| question.push(c as u8));
-        question.push(0);
-
-
         MdnsQuery {
             header: DnsHeader::new_mdns(),
-
-            qname: question,
-            qtype: 0x000cu16.to_be(), // PTR
-            qclass: 0x0001u16.to_be() // IN (ARPA)
+            question: DnsQuestion {
+                qname: encode_name(&reverse_name(ip)),
+                qtype: TYPE_PTR,
+                qclass: 0x0001 // IN (ARPA)
+            },
         }
     }
-    fn header_as_slice(&self) -> &[u8; DnsHeader::SIZE] {
-        unsafe {
-            &*(self as *const Self as *const [u8; DnsHeader::SIZE])
+    /// DNS-SD service type enumeration (_services._dns-sd._udp.local), which is answered by most
+    /// of mDNS responders. Unicast response is requested.
+    fn services() -> Self {
+        MdnsQuery {
+            header: DnsHeader::new_mdns(),
+            question: DnsQuestion {
+                qname: encode_name("_services._dns-sd._udp.local"),
+                qtype: TYPE_PTR,
+                qclass: 0x8001 // IN with UNICAST-RESPONSE flag
+            },
         }
     }
-
     fn to_packet(&self) -> Vec<u8> {
-        let mut tmp_vec: Vec<u8> = vec![];
-        self.header_as_slice().map(|b| tmp_vec.push(b));
-        self.qname.iter().for_each(|b| tmp_vec.push(*b));
+        self.header.to_packet(&self.question)
+    }
 
-        tmp_vec.push(self.qtype as u8);
-        tmp_vec.push((self.qtype >> 8) as u8);
+    /// Asks `addr` for its domain name. `scope_id` is the interface index for link-local IPv6
+    /// addresses, it's ignored for IPv4. Returns the name with number of sent requests.
+    /// With `ScanConfig::mdns_multicast` question is sent to multicast group, see `send_multicast`.
+    pub async fn send(addr: IpAddr, scope_id: u32, session: &Session) -> Result<Option<(String, u32)>, AppError> {
+        if session.config.mdns_multicast {
+            return Self::send_multicast(addr, scope_id, session).await;
+        }
+        let request = Self::new(addr).to_packet();
 
-        tmp_vec.push(self.qclass as u8);
-        tmp_vec.push((self.qclass >> 8) as u8);
+        let remote: SocketAddr = match addr {
+            IpAddr::V4(a) => (a, Self::PORT).into(),
+            IpAddr::V6(a) => SocketAddrV6::new(a, Self::PORT, 0, scope_id).into(),
+        };
+        let answer = query(remote, &request, session.config.mdns_timeout, session).await?;
+        let Some((buff, attempts)) = answer else { return Ok(None) };
+        let name = Self::parse_response(addr, &buff).ok_or(AppError::InvalidResponseMdns)?;
 
-        tmp_vec
+        Ok(Some((name, attempts)))
     }
+    /// Sends the same question as `send` with UNICAST-RESPONSE flag to mDNS multicast group, which is
+    /// answered by responders ignoring unicast queries from other networks. Answers are accepted from
+    /// any source, but only with PTR record for `addr`.
+    async fn send_multicast(addr: IpAddr, scope_id: u32, session: &Session) -> Result<Option<(String, u32)>, AppError> {
+        let mut query = Self::new(addr);
+        query.question.qclass |= 0x8000;
+        let request = query.to_packet();
+
+        let (local_addr, group): (&str, SocketAddr) = match addr {
+            IpAddr::V4(_) => ("0.0.0.0:0", (Self::MULTICAST_V4, Self::PORT).into()),
+            IpAddr::V6(_) => ("[::]:0", SocketAddrV6::new(Self::MULTICAST_V6, Self::PORT, 0, scope_id).into()),
+        };
+        let sock = UdpSocket::bind(local_addr).await.map_err(|_| AppError::SocketCreate)?;
+        if addr.is_ipv4() {
+            // RFC 6762 11, link-local packets are sent with TTL 255
+            sock.set_multicast_ttl_v4(255).map_err(|_| AppError::SocketCreate)?;
+        }
 
-    pub fn send(addr: IpAddr) -> Result<Option<String>, AppError> {
-        let request = Self::new(addr).to_packet();
+        let mut answer = None;
+        query_each(&sock, group, &request, session.config.mdns_timeout, session, |datagram, _, attempt| {
+            match Self::parse_response(addr, datagram) {
+                Some(name) => {
+                    answer = Some((name, attempt));
+                    Receiving::Done
+                },
+                None => Receiving::Continue,
+            }
+        }).await?;
 
-        let buff = query(addr, Self::PORT, &request)?;
-        if buff.is_none() { return Ok(None) };
-        let buff = buff.unwrap();
-        if buff.len() <= request.len() + Self::MIN_RESPONSE_SIZE { return Err(AppError::InvalidResponseMdns )};
-
-        // response contains request + response name [u8; 2] + response type [u8; 2] + cache flush [u8; 2] + time to live [u8; 4] + answer
-        // so actual response is at buff[(request.len() + 10)..]
-        let (_, mut response) = buff.split_at(request.len() + 10);
-        // the next two bytes correspond to the answer size
-        let answer_size: u16 = ((response[0] as u16) << 8) | response[1] as u16;
-
-        // name consists of the words (ASCII octet strings, preceded by their size)
-        // for example "abc.com" would be 0x03 0x41 0x42 0x43 0x03 0x43 0x6f 0x6d 0x00
-        // for local domain names last word is "local"
-
-        let mut word_size = response[2]; // size of first array of octets
-        response = &response[3..]; // move slice start to exclude previously obtained data
-
-        let mut name = String::new();
-
-        // start from the first array of characters
-        response[..].iter()
-            .take((answer_size - 2) as usize) // ignore NULL-terminator
-            .for_each(|&b| {
-                if word_size == 0 {
-                    word_size = b; // update word size to next word
-                    name.push('.');
-                } else {
-                    word_size -= 1;
-                    if b.is_ascii_alphanumeric() || b.is_ascii_punctuation() {
-                        name.push(b as char);
-                    }
-                };
+        Ok(answer)
+    }
+    /// Finds domain name of `addr` in `response`. Answer is PTR record with the same name as in the
+    /// question, but it's not necessary in answers section, and the question may be not echoed back.
+    fn parse_response(addr: IpAddr, response: &[u8]) -> Option<String> {
+        let response = Message::parse(response)?;
+        if !response.is_response() { return None };
+
+        let question = reverse_name(addr);
+        let name = response.records()
+            .filter(|r| r.name.eq_ignore_ascii_case(&question))
+            .find_map(|r| match &r.data {
+                RecordData::Ptr(name) => Some(name.clone()),
+                _ => None,
             });
+        name
+    }
+
+    /// Sends service enumeration query to IPv6 mDNS multicast group on network interface with
+    /// `scope_id` index and collects addresses of every host, which answered in time.
+    pub async fn discover_v6(scope_id: u32, session: &Session) -> Result<Vec<SocketAddrV6>, AppError> {
+        let request = Self::services().to_packet();
+        let group = SocketAddrV6::new(Self::MULTICAST_V6, Self::PORT, 0, scope_id);
+        let sock = UdpSocket::bind("[::]:0").await.map_err(|_| AppError::SocketCreate)?;
+
+        let mut hosts = Vec::new();
+        query_each(&sock, group.into(), &request, session.config.mdns_timeout, session, |_, from, _| {
+            let SocketAddr::V6(from) = from else { return Receiving::Continue };
+            if !hosts.contains(&from) { hosts.push(from) };
+            Receiving::Answered
+        }).await?;
+
+        Ok(hosts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reverse_query_packet() {
+        let query = MdnsQuery::new(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 10)));
+
+        let mut expected = b"\x00\x00\x00\x00\x00\x01\x00\x00\x00\x00\x00\x00".to_vec();
+        expected.extend_from_slice(b"\x0210\x011\x03168\x03192\x07in-addr\x04arpa\x00\x00\x0c\x00\x01");
+        assert_eq!(query.to_packet(), expected);
+    }
 
-        Ok(Some(name))
+    #[test]
+    fn services_query_packet() {
+        let mut expected = b"\x00\x00\x00\x00\x00\x01\x00\x00\x00\x00\x00\x00".to_vec();
+        expected.extend_from_slice(b"\x09_services\x07_dns-sd\x04_udp\x05local\x00\x00\x0c\x80\x01");
+        assert_eq!(MdnsQuery::services().to_packet(), expected);
     }
 }
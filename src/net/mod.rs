@@ -1,21 +1,106 @@
 pub mod nbns;
 pub mod mdns;
+pub mod llmnr;
+pub mod listen;
+pub mod dns;
+pub mod neighbors;
 
-use std::net::{UdpSocket, IpAddr};
-use nbns::NbnsAnswer;
-use crate::{AppError, AppendNewline};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use tokio::net::UdpSocket;
+use tokio::sync::oneshot;
+use nbns::{HostRole, NbAddress, NbnsAnswer, NbnsStatistics, NetbiosName, NodeStatus};
+use crate::{AppError, ScanConfig};
+use crate::utils::{AppendNewline, csv_field, json_string, json_optional_string};
 
 
-pub const RECV_BUFF_SIZE: usize = 256;
-pub const TOO_LOW_TIMEOUT_WARNING_MS: u64 = 100;
-pub const TOO_BIG_TIMEOUT_WARNING_MS: u64 = 3500;
-const DEFAULT_TIMEOUT_MS: u64 = 500;
-pub static mut TIMEOUT: std::time::Duration = std::time::Duration::from_millis(DEFAULT_TIMEOUT_MS);
+// mDNS messages may be up to 9000 bytes, and node status of host with many names doesn't fit to
+// the minimal 576 bytes datagram either
+pub const RECV_BUFF_SIZE: usize = 9000;
+
+/// Name resolution protocols used for querying hosts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Protocol {
+    Nbns,
+    Mdns,
+    Llmnr,
+}
+impl Protocol {
+    pub const ALL: [Protocol; 3] = [Protocol::Nbns, Protocol::Mdns, Protocol::Llmnr];
+}
+impl std::fmt::Display for Protocol {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", match self {
+            Protocol::Nbns => "NBNS",
+            Protocol::Mdns => "mDNS",
+            Protocol::Llmnr => "LLMNR",
+        })
+    }
+}
+impl std::str::FromStr for Protocol {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "nbns" => Ok(Protocol::Nbns),
+            "mdns" => Ok(Protocol::Mdns),
+            "llmnr" => Ok(Protocol::Llmnr),
+            _ => Err(AppError::ParseProtocol),
+        }
+    }
+}
+
+/// Optional columns of CSV and TSV output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Column {
+    Mac,
+    /// Where MAC address was found: NBNS or neighbor table
+    MacSource,
+    /// Vendor of network adapter found by MAC address
+    Vendor,
+    Workgroup,
+    /// Every NetBIOS name with its service, like `HOST<20>`
+    Names,
+    Roles,
+    /// Protocols, which answered, like `NBNS mDNS`
+    Protocols,
+}
+impl Column {
+    fn title(&self) -> &'static str {
+        match self {
+            Column::Mac => "mac",
+            Column::MacSource => "mac_source",
+            Column::Vendor => "vendor",
+            Column::Workgroup => "workgroup",
+            Column::Names => "names",
+            Column::Roles => "roles",
+            Column::Protocols => "protocols",
+        }
+    }
+}
+impl std::str::FromStr for Column {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mac" => Ok(Column::Mac),
+            "mac_source" => Ok(Column::MacSource),
+            "vendor" => Ok(Column::Vendor),
+            "workgroup" => Ok(Column::Workgroup),
+            "names" => Ok(Column::Names),
+            "roles" => Ok(Column::Roles),
+            "protocols" => Ok(Column::Protocols),
+            _ => Err(AppError::ParseColumn),
+        }
+    }
+}
 
 // DOMAIN NAMES - IMPLEMENTATION and SPECIFICATION  https://www.rfc-editor.org/rfc/rfc883
 // DOMAIN NAMES - CONCEPTS AND FACILITIES           https://www.rfc-editor.org/rfc/rfc1034
 
-#[repr(C)]
+/// Header of DNS-like packets. Fields are in host byte order, and are converted to network byte
+/// order only while writing packet.
 struct DnsHeader {
     trans_id: u16,
     flags: u16,
@@ -25,30 +110,59 @@ struct DnsHeader {
     arcount: u16,
 }
 impl DnsHeader {
-    pub const SIZE: usize = std::mem::size_of::<Self>();
+    pub const SIZE: usize = 12;
 
     fn new_nbns() -> Self {
         Self {
-            trans_id: rand::random::<u16>().to_be(),
-            flags:    0u16.to_be(), // unicast
-            qdcount:  1u16.to_be(),
-            ancount:  0u16.to_be(),
-            nscount:  0u16.to_be(),
-            arcount:  0u16.to_be(),
+            trans_id: rand::random::<u16>(),
+            flags:    0, // unicast
+            qdcount:  1,
+            ancount:  0,
+            nscount:  0,
+            arcount:  0,
+        }
+    }
+    fn new_llmnr() -> Self {
+        Self {
+            trans_id: rand::random::<u16>(),
+            flags:    0, // standard query without conflict detection
+            qdcount:  1,
+            ancount:  0,
+            nscount:  0,
+            arcount:  0,
         }
     }
     fn new_mdns() -> Self {
         Self {
-            trans_id: 0u16.to_be(), // should be 0 for mdns
-            flags:    0u16.to_be(), // unicast
-            qdcount:  1u16.to_be(),
-            ancount:  0u16.to_be(),
-            nscount:  0u16.to_be(),
-            arcount:  0u16.to_be(),
+            trans_id: 0, // should be 0 for mdns
+            flags:    0, // unicast
+            qdcount:  1,
+            ancount:  0,
+            nscount:  0,
+            arcount:  0,
+        }
+    }
+    /// Encodes request with this header and single `question`, every field is in network byte order.
+    fn to_packet(&self, question: &DnsQuestion) -> Vec<u8> {
+        let mut packet = Vec::with_capacity(Self::SIZE + question.qname.len() + 4);
+        for field in [self.trans_id, self.flags, self.qdcount, self.ancount, self.nscount, self.arcount] {
+            packet.extend_from_slice(&field.to_be_bytes());
         }
+        packet.extend_from_slice(&question.qname);
+        packet.extend_from_slice(&question.qtype.to_be_bytes());
+        packet.extend_from_slice(&question.qclass.to_be_bytes());
+        packet
     }
 }
 
+/// Question of DNS-like request with already encoded name.
+struct DnsQuestion {
+    qname: Vec<u8>,
+    qtype: u16,
+    qclass: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct MacAddress (u8, u8, u8, u8, u8, u8);
 impl MacAddress {
     /// Constructs `MacAddress`. Returns `None` if slice has not 6 bytes.
@@ -57,6 +171,18 @@ impl MacAddress {
 
         Some( MacAddress (bytes[0], bytes[1], bytes[2], bytes[3], bytes[4], bytes[5]))
     }
+    pub fn octets(&self) -> [u8; 6] {
+        [self.0, self.1, self.2, self.3, self.4, self.5]
+    }
+    /// Organizationally unique identifier, which is the first three octets.
+    pub fn oui(&self) -> [u8; 3] {
+        [self.0, self.1, self.2]
+    }
+    /// Address isn't assigned by the vendor, but set by administrator or randomized by the system
+    /// for privacy. U/L bit of the first octet is set in such addresses.
+    pub fn is_locally_administered(&self) -> bool {
+        self.0 & 0x02 != 0
+    }
 }
 impl std::fmt::Display for MacAddress {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -66,74 +192,239 @@ impl std::fmt::Display for MacAddress {
     }
 }
 
+/// Where MAC address of the host was found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MacSource {
+    /// Reported by the host in NetBIOS node status
+    Nbns,
+    /// Kernel neighbor table (ARP or NDP cache) of the local link
+    NeighborTable,
+}
+impl std::fmt::Display for MacSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", match self {
+            MacSource::Nbns => "NBNS",
+            MacSource::NeighborTable => "neighbor table",
+        })
+    }
+}
+
 /// Result of querying single host
+#[derive(Clone)]
 pub struct QueryResult {
     ip_addr: std::net::IpAddr,
     host_names: Vec<NbnsAnswer>,
-    domain_name: String
+    nbns_statistics: Option<NbnsStatistics>,
+    domain_name: String,
+    llmnr_name: String,
+    /// MAC address from neighbor table, used if NBNS didn't provide it
+    neighbor_mac: Option<MacAddress>,
+    vendor: Option<String>,
+    attempts: Vec<(Protocol, u32)>,
+    /// Protocols, which answered, or which were heard while listening
+    protocols: Vec<Protocol>,
+    errors: Vec<AppError>,
 }
 impl QueryResult {
     const PADDING_IP4: usize = 16;
     const PADDING_IP6: usize = 36;
     const PADDING_HOSTNAME: usize = 16;
+    const PADDING_WORKGROUP: usize = 16;
     const PADDING_DOMAIN_NAME: usize = 20;
+    const PADDING_MAC: usize = 18;
 
-    pub fn new(ip_addr: std::net::IpAddr) -> Self {
+    pub(crate) fn new(ip_addr: std::net::IpAddr) -> Self {
         QueryResult {
             ip_addr,
             host_names: Vec::new(),
-            domain_name: String::new()
+            nbns_statistics: None,
+            domain_name: String::new(),
+            llmnr_name: String::new(),
+            neighbor_mac: None,
+            vendor: None,
+            attempts: Vec::new(),
+            protocols: Vec::new(),
+            errors: Vec::new(),
         }
     }
+    /// Returns `true` if host didn't provide any names. It still may have errors.
     pub fn is_empty(&self) -> bool {
-        self.host_names.is_empty() && self.domain_name.is_empty()
+        self.host_names.is_empty() && self.domain_name.is_empty() && self.llmnr_name.is_empty()
+    }
+    /// Adds NetBIOS `name`, if it's not known yet. Returns `true` if it was added.
+    pub(crate) fn add_hostname(&mut self, name: NbnsAnswer) -> bool {
+        if self.host_names.contains(&name) { return false };
+        self.host_names.push(name);
+        true
     }
-    pub fn push_hostname(&mut self, hostname: NbnsAnswer) {
-        self.host_names.push(hostname);
+    /// Adds every name of NetBIOS node status and its statistics.
+    pub(crate) fn set_node_status(&mut self, status: NodeStatus) {
+        self.host_names.extend(status.names);
+        self.nbns_statistics = status.statistics;
     }
-    pub fn set_domain_name(&mut self, domain_name: String) {
+    pub(crate) fn set_domain_name(&mut self, domain_name: String) {
         self.domain_name = domain_name;
     }
+    pub(crate) fn set_llmnr_name(&mut self, llmnr_name: String) {
+        self.llmnr_name = llmnr_name;
+    }
+    pub(crate) fn set_neighbor_mac(&mut self, mac: MacAddress) {
+        self.neighbor_mac = Some(mac);
+    }
+    pub(crate) fn set_vendor(&mut self, vendor: String) {
+        self.vendor = Some(vendor);
+    }
+    pub(crate) fn set_attempts(&mut self, protocol: Protocol, attempts: u32) {
+        self.attempts.retain(|(p, _)| *p != protocol);
+        self.attempts.push((protocol, attempts));
+        self.add_protocol(protocol);
+    }
+    /// Marks `protocol` as answered without any requests, like when the host was only heard.
+    pub(crate) fn add_protocol(&mut self, protocol: Protocol) {
+        if !self.protocols.contains(&protocol) { self.protocols.push(protocol) };
+    }
+    pub(crate) fn push_error(&mut self, error: AppError) {
+        self.errors.push(error);
+    }
+
+    pub fn ip_addr(&self) -> IpAddr {
+        self.ip_addr
+    }
+    /// Every answer of NetBIOS node status request, including MAC address.
+    pub fn host_names(&self) -> &[NbnsAnswer] {
+        &self.host_names
+    }
+    /// The first NetBIOS name, which is usually the workstation name, or LLMNR name if host
+    /// doesn't use NetBIOS.
+    pub fn hostname(&self) -> Option<&str> {
+        self.host_names.iter().find_map(|n| n.name()).or(self.llmnr_name())
+    }
+    /// Name from LLMNR reverse query.
+    pub fn llmnr_name(&self) -> Option<&str> {
+        if self.llmnr_name.is_empty() { None } else { Some(&self.llmnr_name) }
+    }
+    /// Roles of the host derived from services of its NetBIOS names, without duplicates.
+    pub fn roles(&self) -> Vec<HostRole> {
+        let mut roles = Vec::new();
+        for role in self.host_names.iter().filter_map(|n| n.service_type()?.role()) {
+            if !roles.contains(&role) { roles.push(role) };
+        }
+        roles
+    }
+    /// Statistics from NetBIOS node status, if host sent them.
+    pub fn nbns_statistics(&self) -> Option<&NbnsStatistics> {
+        self.nbns_statistics.as_ref()
+    }
+    /// Workgroup or NT domain, which is the group name with workstation service (0x00).
+    pub fn workgroup(&self) -> Option<&str> {
+        self.host_names.iter()
+            .find(|n| n.is_group() && n.service() == Some(0x00))
+            .and_then(|n| n.name())
+    }
+    /// Returns `true` if host is in `workgroup`, names are compared case-insensitively.
+    pub fn in_workgroup(&self, workgroup: &str) -> bool {
+        self.workgroup().is_some_and(|w| w.eq_ignore_ascii_case(workgroup))
+    }
+    pub fn domain_name(&self) -> Option<&str> {
+        if self.domain_name.is_empty() { None } else { Some(&self.domain_name) }
+    }
+    /// MAC address from NetBIOS node status, or from neighbor table if host didn't report it.
+    pub fn mac_address(&self) -> Option<&MacAddress> {
+        self.nbns_mac().or(self.neighbor_mac.as_ref())
+    }
+    /// Where `mac_address` was found.
+    pub fn mac_source(&self) -> Option<MacSource> {
+        match (self.nbns_mac(), self.neighbor_mac) {
+            (Some(_), _) => Some(MacSource::Nbns),
+            (None, Some(_)) => Some(MacSource::NeighborTable),
+            (None, None) => None,
+        }
+    }
+    fn nbns_mac(&self) -> Option<&MacAddress> {
+        self.host_names.iter().find_map(|n| match n {
+            NbnsAnswer::Mac(mac) => Some(mac),
+            _ => None,
+        })
+    }
+    /// Vendor of network adapter, if MAC address is known and found in OUI database.
+    pub fn vendor(&self) -> Option<&str> {
+        self.vendor.as_deref()
+    }
+    /// Vendor, or note that MAC address is locally administered, for table and CSV output.
+    fn vendor_label(&self) -> Option<&str> {
+        match self.mac_address() {
+            Some(mac) if mac.is_locally_administered() => Some("(locally administered)"),
+            _ => self.vendor(),
+        }
+    }
+    /// How many requests were sent before `protocol` answered. `None` if it didn't answer.
+    pub fn attempts(&self, protocol: Protocol) -> Option<u32> {
+        self.attempts.iter().find(|(p, _)| *p == protocol).map(|(_, a)| *a)
+    }
+    /// Protocols, which answered, in order of querying.
+    pub fn protocols(&self) -> Vec<Protocol> {
+        self.protocols.clone()
+    }
+    /// Errors of every protocol, which failed while querying the host.
+    pub fn errors(&self) -> &[AppError] {
+        &self.errors
+    }
+    /// Single error describing failed querying: the error itself, if only one protocol failed, or
+    /// `AppError::InvalidResponses` otherwise.
+    pub fn error(&self) -> Option<AppError> {
+        match self.errors.len() {
+            0 => None,
+            1 => Some(self.errors[0]),
+            _ => Some(AppError::InvalidResponses),
+        }
+    }
 
     // Different padding is needed for IPv4 and IPv6
-    fn format_row<A, B, C>(a: A, b: B, c: C, is_ipv6: bool) -> String
-    where A: std::fmt::Display, B: std::fmt::Display, C: std::fmt::Display
-    {
+    /// Cells are IP address, hostname, workgroup, domain name, MAC address and vendor.
+    /// The last one isn't padded, because vendor names differ in length too much.
+    fn format_row(cells: [&str; 6], is_ipv6: bool) -> String {
+        let [ip, hostname, workgroup, domain_name, mac, vendor] = cells;
         format!(
-            "{:<ip_width$} {:<hostname_width$} {:<domain_name_width$}",
-            a, b, c,
+            "{:<ip_width$} {:<hostname_width$} {:<workgroup_width$} {:<domain_name_width$} {:<mac_width$} {}",
+            ip, hostname, workgroup, domain_name, mac, vendor,
 
             ip_width = match is_ipv6 {
                 false => Self::PADDING_IP4,
                 true  => Self::PADDING_IP6,
             },
             hostname_width = Self::PADDING_HOSTNAME,
+            workgroup_width = Self::PADDING_WORKGROUP,
             domain_name_width = Self::PADDING_DOMAIN_NAME,
+            mac_width = Self::PADDING_MAC,
         )
     }
     pub fn table_head(addr: &std::net::IpAddr) -> String {
-        Self::format_row("IP address", "Hostname", "Domain name", addr.is_ipv6())
+        Self::format_row(
+            ["IP address", "Hostname", "Workgroup", "Domain name", "MAC address", "Vendor"],
+            addr.is_ipv6(),
+        )
     }
     /// Format result as table row. Panics if called on empty `QueryResult`.
     pub fn table_row(&self) -> String {
+        self.table_row_padded(self.ip_addr.is_ipv6())
+    }
+    /// Same as `table_row`, but IP address is padded for IPv6 even if it's IPv4, so rows of both
+    /// are aligned under `table_head` of IPv6 address.
+    pub fn table_row_wide(&self) -> String {
+        self.table_row_padded(true)
+    }
+    fn table_row_padded(&self, is_ipv6: bool) -> String {
         assert!(!self.is_empty());
 
-        let hostname = match self.host_names.first() {
-            Some(n) => n.to_string(),
-            None => "-".to_string()
-        };
-
-        let domain_name = if !self.domain_name.is_empty() {
-            self.domain_name.to_string()
-        } else {
-            "-".to_string()
-        };
+        let hostname = self.hostname().unwrap_or("-");
+        let workgroup = self.workgroup().unwrap_or("-");
+        let domain_name = self.domain_name().unwrap_or("-");
+        let mac = self.mac_address().map(|m| m.to_string());
+        let vendor = self.vendor_label().unwrap_or("-");
 
         Self::format_row(
-            self.ip_addr,
-            hostname,
-            domain_name,
-            self.ip_addr.is_ipv6(),
+            [&self.ip_addr.to_string(), hostname, workgroup, domain_name, mac.as_deref().unwrap_or("-"), vendor],
+            is_ipv6,
         )
     }
     /// Verbosely formats result. Panics if called on empty `QueryResult`.
@@ -152,55 +443,480 @@ impl QueryResult {
             res.new_line();
         }
 
+        let roles = self.roles();
+        if !roles.is_empty() {
+            let roles: Vec<String> = roles.iter().map(|r| r.to_string()).collect();
+            res.push_str(&format!("Roles: {}", roles.join(", ")));
+            res.new_line();
+        }
+
+        if let (Some(mac), Some(MacSource::NeighborTable)) = (self.mac_address(), self.mac_source()) {
+            res.push_str(&format!("MAC address: {} (from {})", mac, MacSource::NeighborTable));
+            res.new_line();
+        }
+
+        if let Some(vendor) = self.vendor_label() {
+            res.push_str(&format!("Vendor: {}", vendor));
+            res.new_line();
+        }
+
+        if let Some(statistics) = &self.nbns_statistics {
+            for line in statistics.verbose_lines() {
+                res.push_str(&line);
+                res.new_line();
+            }
+        }
+
         if !self.domain_name.is_empty() {
             res.push_str(&format!("Domain name: {}", self.domain_name));
             res.new_line();
         }
 
+        if !self.llmnr_name.is_empty() {
+            res.push_str(&format!("LLMNR name: {}", self.llmnr_name));
+            res.new_line();
+        }
+
+        if !self.attempts.is_empty() {
+            let attempts: Vec<String> = self.attempts.iter()
+                .map(|(protocol, attempts)| format!("{} {}", protocol, attempts))
+                .collect();
+            res.push_str(&format!("Attempts: {}", attempts.join(", ")));
+            res.new_line();
+        }
+
         res.push_str(&format!("{:=>1$}", "", 40));
         res.new_line();
         res.new_line();
 
         res
     }
-}
+    /// Header of CSV or TSV table, fields are separated by `separator`.
+    pub fn csv_head(columns: &[Column], separator: char) -> String {
+        let mut head = vec!["ip", "hostname", "domain_name"];
+        head.extend(columns.iter().map(|c| c.title()));
+        head.join(&separator.to_string())
+    }
+    /// Formats result as CSV or TSV row with the same `columns` as in `csv_head`.
+    /// Missing values are left empty.
+    pub fn csv_row(&self, columns: &[Column], separator: char) -> String {
+        let mut row = vec![
+            self.ip_addr.to_string(),
+            self.hostname().unwrap_or_default().to_string(),
+            self.domain_name().unwrap_or_default().to_string(),
+        ];
+        for column in columns {
+            row.push(match column {
+                Column::Mac => self.mac_address().map(|m| m.to_string()).unwrap_or_default(),
+                Column::MacSource => self.mac_source().map(|s| s.to_string()).unwrap_or_default(),
+                Column::Vendor => self.vendor_label().unwrap_or_default().to_string(),
+                Column::Workgroup => self.workgroup().unwrap_or_default().to_string(),
+                Column::Names => self.host_names.iter()
+                    .filter_map(|n| Some(format!("{}<{:02x}>", n.name()?, n.service()?)))
+                    .collect::<Vec<String>>()
+                    .join(" "),
+                Column::Roles => self.roles().iter()
+                    .map(|r| r.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", "),
+                Column::Protocols => self.protocols().iter()
+                    .map(|p| p.to_string())
+                    .collect::<Vec<String>>()
+                    .join(" "),
+            });
+        }
 
-/// Set timeout used for `query` function. Returns `AppError::SocketError` if `timeout` is 0.
-pub fn set_timeout_from_millis(timeout: u64) -> Result<(), AppError> {
-    if timeout == 0 {
-        return Err(AppError::SocketTimeout);
+        row.iter()
+            .map(|field| csv_field(field, separator))
+            .collect::<Vec<String>>()
+            .join(&separator.to_string())
     }
-    unsafe {
-        TIMEOUT = std::time::Duration::from_millis(timeout);
+    /// Formats error of querying as JSON object with address and error message.
+    /// Returns `None` if there were no errors.
+    pub fn error_to_json(&self) -> Option<String> {
+        Some(format!(
+            "{{\"ip\":{},\"error\":{}}}",
+            json_string(&self.ip_addr.to_string()),
+            json_string(&self.error()?.to_string()),
+        ))
+    }
+    /// Formats result as JSON object with address, every NetBIOS name, workgroup, MAC address with
+    /// vendor, domain name, LLMNR name and answered protocols.
+    pub fn to_json(&self) -> String {
+        let names: Vec<String> = self.host_names.iter()
+            .filter_map(|n| Some(format!(
+                "{{\"name\":{},\"service\":{},\"service_type\":{},\"unique\":{},\"group\":{},\"permanent\":{}}}",
+                json_string(n.name()?),
+                n.service()?,
+                json_string(&n.service_type()?.to_string()),
+                !n.is_group(),
+                n.is_group(),
+                n.is_permanent(),
+            )))
+            .collect();
+
+        let roles: Vec<String> = self.roles().iter()
+            .map(|r| json_string(&r.to_string()))
+            .collect();
+        let protocols: Vec<String> = self.protocols().iter()
+            .map(|p| json_string(&p.to_string()))
+            .collect();
+
+        format!(
+            "{{\"ip\":{},\"names\":[{}],\"roles\":[{}],\"workgroup\":{},\"mac\":{},\"mac_source\":{},\"mac_local\":{},\"vendor\":{},\"domain_name\":{},\"llmnr_name\":{},\"protocols\":[{}]}}",
+            json_string(&self.ip_addr.to_string()),
+            names.join(","),
+            roles.join(","),
+            json_optional_string(self.workgroup()),
+            json_optional_string(self.mac_address().map(|m| m.to_string()).as_deref()),
+            json_optional_string(self.mac_source().map(|s| s.to_string()).as_deref()),
+            self.mac_address().is_some_and(|m| m.is_locally_administered()),
+            json_optional_string(self.vendor()),
+            json_optional_string(self.domain_name()),
+            json_optional_string(self.llmnr_name()),
+            protocols.join(","),
+        )
     }
-    Ok(())
 }
-fn get_timeout() -> std::time::Duration {
-    unsafe {
-        TIMEOUT
+
+/// Result of name lookup: the name and addresses of its owners found by NetBIOS and LLMNR.
+#[derive(Debug, Clone)]
+pub struct NameLookup {
+    pub name: NetbiosName,
+    /// Owners found by NetBIOS name query with their NB flags
+    pub nbns: Vec<NbAddress>,
+    /// Addresses found by LLMNR A and AAAA queries
+    pub llmnr: Vec<IpAddr>,
+}
+impl NameLookup {
+    const PADDING_IP: usize = 40; // fits any IPv6 address
+    const PADDING_PROTOCOL: usize = 9;
+    const PADDING_NODE_TYPE: usize = 10;
+
+    /// Returns `true` if nobody answered.
+    pub fn is_empty(&self) -> bool {
+        self.nbns.is_empty() && self.llmnr.is_empty()
+    }
+    /// Every address with protocol, which found it, and NB flags for NetBIOS.
+    fn addresses(&self) -> Vec<(IpAddr, Protocol, Option<&NbAddress>)> {
+        self.nbns.iter()
+            .map(|a| (IpAddr::V4(a.addr), Protocol::Nbns, Some(a)))
+            .chain(self.llmnr.iter().map(|a| (*a, Protocol::Llmnr, None)))
+            .collect()
+    }
+
+    fn format_row(ip: &str, protocol: &str, node_type: &str, group: &str) -> String {
+        format!(
+            "{:<ip_width$} {:<protocol_width$} {:<node_width$} {}",
+            ip, protocol, node_type, group,
+
+            ip_width = Self::PADDING_IP,
+            protocol_width = Self::PADDING_PROTOCOL,
+            node_width = Self::PADDING_NODE_TYPE,
+        )
+    }
+    pub fn table_head() -> String {
+        Self::format_row("IP address", "Protocol", "Node type", "Group")
+    }
+    /// Formats every address as table row. LLMNR addresses have no node type and group flag.
+    pub fn table_rows(&self) -> Vec<String> {
+        self.addresses().into_iter()
+            .map(|(ip, protocol, nb)| Self::format_row(
+                &ip.to_string(),
+                &protocol.to_string(),
+                &nb.map(|a| format!("{}-node", a.node_type)).unwrap_or("-".to_string()),
+                nb.map(|a| if a.group { "yes" } else { "no" }).unwrap_or("-"),
+            ))
+            .collect()
+    }
+    pub fn csv_head(separator: char) -> String {
+        ["name", "service", "ip", "protocol", "node_type", "group"].join(&separator.to_string())
+    }
+    /// Formats every address as CSV or TSV row, fields are separated by `separator`.
+    /// Missing values are left empty.
+    pub fn csv_rows(&self, separator: char) -> Vec<String> {
+        self.addresses().into_iter()
+            .map(|(ip, protocol, nb)| {
+                [
+                    self.name.name.clone(),
+                    self.name.suffix.to_string(),
+                    ip.to_string(),
+                    protocol.to_string(),
+                    nb.map(|a| a.node_type.to_string()).unwrap_or_default(),
+                    nb.map(|a| a.group.to_string()).unwrap_or_default(),
+                ]
+                    .iter()
+                    .map(|field| csv_field(field, separator))
+                    .collect::<Vec<String>>()
+                    .join(&separator.to_string())
+            })
+            .collect()
+    }
+    /// Formats the name with every address as JSON object.
+    pub fn to_json(&self) -> String {
+        let addresses: Vec<String> = self.addresses().into_iter()
+            .map(|(ip, protocol, nb)| format!(
+                "{{\"ip\":{},\"protocol\":{},\"node_type\":{},\"group\":{}}}",
+                json_string(&ip.to_string()),
+                json_string(&protocol.to_string()),
+                json_optional_string(nb.map(|a| a.node_type.to_string()).as_deref()),
+                nb.map(|a| a.group.to_string()).unwrap_or("null".to_string()),
+            ))
+            .collect();
+
+        format!(
+            "{{\"name\":{},\"service\":{},\"addresses\":[{}]}}",
+            json_string(&self.name.name),
+            self.name.suffix,
+            addresses.join(","),
+        )
     }
 }
 
-/// Make network connection with `addr` on `port` and sends `request`, then listens for answer on same
-/// address and port. If recived answer, returns it. If no answers were recived, returns `Ok(None)`.
+/// Make network connection with `addr` and sends `request`, then listens for answer on same
+/// address for `timeout`. Unanswered `request` is sent again as many times as `session` allows.
+/// If recived answer, returns it with number of attempts. If no answers were recived, returns `Ok(None)`.
 /// If some error occurred while communicating, returns `AppError`.
-fn query(addr: IpAddr, port: u16, request: &[u8]) -> Result<Option<Vec<u8>>, AppError> {
-    let sock = UdpSocket::bind("0.0.0.0:0").map_err(|_| AppError::SocketCreate)?;
+async fn query(addr: SocketAddr, request: &[u8], timeout: std::time::Duration, session: &Session) -> Result<Option<(Vec<u8>, u32)>, AppError> {
+    let local_addr = match addr {
+        SocketAddr::V4(_) => "0.0.0.0:0",
+        SocketAddr::V6(_) => "[::]:0",
+    };
+    let sock = UdpSocket::bind(local_addr).await.map_err(|_| AppError::SocketCreate)?;
 
-    if let Err(_) = sock.connect((addr, port)) {
+    if sock.connect(addr).await.is_err() {
         return Err(AppError::SocketConnect);
     }
 
-    let timeout = get_timeout();
-    if sock.set_write_timeout(Some(timeout)).is_err() { return Err(AppError::SocketTimeout) };
-    if sock.set_read_timeout (Some(timeout)).is_err() { return Err(AppError::SocketTimeout) };
+    let mut response = [0; RECV_BUFF_SIZE];
+    for attempt in 1..=session.attempts() {
+        session.before_attempt(attempt).await;
+        if sock.send(request).await.is_err() { return Err(AppError::SocketSend) };
 
-    if sock.send(request).is_err() { return Err(AppError::SocketSend) };
+        match tokio::time::timeout(timeout, sock.recv(&mut response)).await {
+            Ok(Ok(size)) => return Ok(Some((response[..size].to_vec(), attempt))),
+            // refused by the host, there is no reason to ask again
+            Ok(Err(_)) => return Ok(None),
+            Err(_) => {},
+        }
+    }
 
-    let mut response = [0; RECV_BUFF_SIZE];
-    if sock.recv(&mut response).is_err() {
-        return Ok(None);
-    };
+    Ok(None)
+}
+
+/// What `query_each` does after passing received datagram to the caller.
+pub(crate) enum Receiving {
+    /// Keep receiving, the request is sent again if nothing answers it in time
+    Continue,
+    /// Keep receiving until timeout, the request is answered and isn't sent again
+    Answered,
+    /// Stop receiving, the request is completely answered
+    Done,
+}
+
+/// Sends `request` from `sock` to `remote`, which may be broadcast or multicast address, and passes
+/// every datagram received for `timeout` to `on_datagram` with its source and number of attempt.
+/// The request is sent again as many times as `session` allows, until `on_datagram` reports that
+/// it's answered. If some error occurred while sending, returns `AppError::SocketSend`.
+async fn query_each<F>(sock: &UdpSocket, remote: SocketAddr, request: &[u8], timeout: std::time::Duration, session: &Session, mut on_datagram: F) -> Result<(), AppError>
+where F: FnMut(&[u8], SocketAddr, u32) -> Receiving
+{
+    let mut buff = [0u8; RECV_BUFF_SIZE];
+    let mut answered = false;
+    for attempt in 1..=session.attempts() {
+        session.before_attempt(attempt).await;
+        if sock.send_to(request, remote).await.is_err() { return Err(AppError::SocketSend) };
+
+        let deadline = tokio::time::Instant::now() + timeout;
+        while let Ok(Ok((size, from))) = tokio::time::timeout_at(deadline, sock.recv_from(&mut buff)).await {
+            match on_datagram(&buff[..size], from, attempt) {
+                Receiving::Continue => {},
+                Receiving::Answered => answered = true,
+                Receiving::Done => return Ok(()),
+            }
+        }
+
+        if answered { break };
+    }
+
+    Ok(())
+}
+
+/// Returns index of network interface with `name`, which may be the index itself.
+/// Names are supported only on Unix-like systems.
+pub fn interface_index(name: &str) -> Option<u32> {
+    if let Ok(index) = name.parse() {
+        return Some(index);
+    }
+
+    #[cfg(unix)]
+    {
+        let name = std::ffi::CString::new(name).ok()?;
+        // SAFETY: `name` is valid NULL-terminated string, which lives during the call
+        let index = unsafe { libc::if_nametoindex(name.as_ptr()) };
+        if index != 0 { return Some(index) };
+    }
+
+    None
+}
+
+/// Everything shared by queries of one scan: `ScanConfig`, socket for NBNS requests and packet
+/// rate limiter.
+pub struct Session {
+    pub config: ScanConfig,
+    pub nbns_sock: SharedSocket,
+    limiter: Option<RateLimiter>,
+}
+impl Session {
+    /// Must be called inside of tokio runtime.
+    pub async fn new(config: &ScanConfig) -> Result<Self, AppError> {
+        Ok(Session {
+            config: config.clone(),
+            nbns_sock: SharedSocket::bind("0.0.0.0:0").await?,
+            limiter: config.rate.map(RateLimiter::new),
+        })
+    }
+    /// Maximum number of times a request is sent.
+    fn attempts(&self) -> u32 {
+        self.config.retries.saturating_add(1)
+    }
+    /// Waits before sending request for `attempt` time, counting from 1.
+    /// Every retry waits twice longer than the previous one, then packet rate limit is applied.
+    async fn before_attempt(&self, attempt: u32) {
+        if attempt > 1 {
+            let backoff = self.config.retry_backoff.saturating_mul(1 << (attempt - 2).min(16));
+            tokio::time::sleep(backoff).await;
+        }
+        self.throttle().await;
+    }
+    /// Waits until the next packet may be sent, if packet rate is limited.
+    pub async fn throttle(&self) {
+        if let Some(limiter) = &self.limiter {
+            limiter.wait().await;
+        }
+    }
+}
+
+/// Spreads packets evenly in time, so no more than given amount of them is sent per second.
+struct RateLimiter {
+    interval: std::time::Duration,
+    next_send: Mutex<tokio::time::Instant>,
+}
+impl RateLimiter {
+    fn new(packets_per_second: u32) -> Self {
+        RateLimiter {
+            interval: std::time::Duration::from_secs(1) / packets_per_second.max(1),
+            next_send: Mutex::new(tokio::time::Instant::now()),
+        }
+    }
+    /// Reserves the next free time slot and waits for it.
+    async fn wait(&self) {
+        let slot = {
+            let mut next_send = self.next_send.lock().unwrap();
+            let slot = (*next_send).max(tokio::time::Instant::now());
+            *next_send = slot + self.interval;
+            slot
+        };
+        tokio::time::sleep_until(slot).await;
+    }
+}
 
-    Ok(Some(response.to_vec()))
+type PendingQueries = Mutex<HashMap<(IpAddr, u16), oneshot::Sender<Vec<u8>>>>;
+
+/// Single UDP socket used for querying many hosts at once, like nbtscan does.
+/// Every answer is matched with the waiting query by its source address and transaction ID, which
+/// is the first two bytes of DNS-like packets.
+/// Incoming datagrams are read by background task, which is stopped on `drop`.
+pub struct SharedSocket {
+    sock: Arc<UdpSocket>,
+    pending: Arc<PendingQueries>,
+    receiver: tokio::task::JoinHandle<()>,
+}
+impl SharedSocket {
+    /// Binds socket to `local_addr`. Must be called inside of tokio runtime.
+    pub async fn bind(local_addr: &str) -> Result<Self, AppError> {
+        let sock = Arc::new(UdpSocket::bind(local_addr).await.map_err(|_| AppError::SocketCreate)?);
+        let pending: Arc<PendingQueries> = Arc::new(Mutex::new(HashMap::new()));
+
+        let receiver = tokio::spawn(Self::receive(sock.clone(), pending.clone()));
+
+        Ok(SharedSocket { sock, pending, receiver })
+    }
+    /// Reads answers until the task is aborted, or until the socket fails, then every waiting
+    /// query times out.
+    async fn receive(sock: Arc<UdpSocket>, pending: Arc<PendingQueries>) {
+        let mut buff = [0u8; RECV_BUFF_SIZE];
+        loop {
+            let (size, from) = match sock.recv_from(&mut buff).await {
+                Ok(received) => received,
+                // ICMP port unreachable from one host shouldn't stop answers from other hosts
+                Err(e) if matches!(e.kind(), std::io::ErrorKind::ConnectionRefused | std::io::ErrorKind::ConnectionReset) => continue,
+                Err(_) => return,
+            };
+            if size < 2 { continue };
+
+            let trans_id = u16::from_be_bytes([buff[0], buff[1]]);
+            let waiting = pending.lock().unwrap().remove(&(from.ip(), trans_id));
+            if let Some(tx) = waiting {
+                // query may be already timed out
+                let _ = tx.send(buff[..size].to_vec());
+            }
+        }
+    }
+
+    /// Sends `request` to `addr` and waits for answer from `addr` with the same transaction ID as in
+    /// `request` for `timeout`. Unanswered `request` is sent again as many times as `session` allows,
+    /// late answer to any of attempts is accepted.
+    /// Returns answer with number of attempts. If no answers were recived, returns `Ok(None)`.
+    pub async fn query(&self, addr: SocketAddr, request: &[u8], timeout: std::time::Duration, session: &Session) -> Result<Option<(Vec<u8>, u32)>, AppError> {
+        if request.len() < 2 { return Err(AppError::SocketSend) };
+        let key = (addr.ip(), u16::from_be_bytes([request[0], request[1]]));
+
+        let (tx, mut rx) = oneshot::channel();
+        self.pending.lock().unwrap().insert(key, tx);
+
+        for attempt in 1..=session.attempts() {
+            session.before_attempt(attempt).await;
+            if self.sock.send_to(request, addr).await.is_err() {
+                self.pending.lock().unwrap().remove(&key);
+                return Err(AppError::SocketSend);
+            }
+
+            match tokio::time::timeout(timeout, &mut rx).await {
+                Ok(Ok(response)) => return Ok(Some((response, attempt))),
+                Ok(Err(_)) => break,
+                Err(_) => {},
+            }
+        }
+
+        self.pending.lock().unwrap().remove(&key);
+        Ok(None)
+    }
+}
+impl Drop for SharedSocket {
+    fn drop(&mut self) {
+        self.receiver.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roles_without_duplicates() {
+        let mut result = QueryResult::new(IpAddr::V4(std::net::Ipv4Addr::new(192, 168, 1, 10)));
+        for name in [
+            NbnsAnswer::Unique(("WIN10-PC".to_string(), 0x00)),
+            NbnsAnswer::Group(("WORKGROUP".to_string(), 0x00)),
+            NbnsAnswer::Unique(("WIN10-PC".to_string(), 0x20)),
+            NbnsAnswer::Unique(("WORKGROUP".to_string(), 0x1d)),
+            NbnsAnswer::Group(("__MSBROWSE__".to_string(), 0x01)),
+            // the same role by another name
+            NbnsAnswer::Unique(("OTHER-PC".to_string(), 0x00)),
+        ] {
+            result.add_hostname(name);
+        }
+        assert_eq!(result.roles(), [HostRole::Workstation, HostRole::FileServer, HostRole::MasterBrowser]);
+    }
 }
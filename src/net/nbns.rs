@@ -1,68 +1,311 @@
 // NetBIOS Name Service
 // https://www.rfc-editor.org/rfc/rfc1002.html
 
-use std::net::IpAddr;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use tokio::net::UdpSocket;
 use crate::AppError;
-use crate::net::{DnsHeader, MacAddress, query};
+use crate::net::{DnsHeader, DnsQuestion, MacAddress, Receiving, Session, query_each};
+use crate::net::dns::{Message, RecordData};
 
-#[repr(C)]
 pub struct NbnsQuery {
     header: DnsHeader,
-
-    // question
-    question: [u8; 34],
-    qtype: u16,
-    qclass: u16,
+    question: DnsQuestion,
 }
-// NODE STATUS REQUEST
+// NODE STATUS REQUEST and NAME QUERY REQUEST
 impl NbnsQuery {
     pub const PORT: u16 = 137; // NetBIOS port
-    pub const SIZE: usize = std::mem::size_of::<NbnsQuery>();
-    const MIN_RESPONSE_SIZE: usize = 32;
+
+    pub(crate) const NB: u16 = 0x0020; // NetBIOS general Name Service Resource Record
+    const NBSTAT: u16 = 0x0021; // NetBIOS NODE STATUS Resource Record
 
     fn new() -> Self {
         // same question is send by nbtscan and nbstat.exe
-        let mut question = [0x41u8; 34];
-        question[0]  = 0x20;
-        question[1]  = 0x43;
-        question[2]  = 0x4b;
-        question[33] = 0;
+        let mut name = [0u8; 16];
+        name[0] = b'*';
 
         NbnsQuery {
             header: DnsHeader::new_nbns(),
+            question: DnsQuestion {
+                qname: Self::encode_name(name).to_vec(),
+                qtype: Self::NBSTAT,
+                qclass: 0x0001
+            },
+        }
+    }
+    /// NAME QUERY REQUEST, RFC 1002 4.2.12. Recursion desired flag is set, so the request may be sent
+    /// to WINS server, and broadcast flag is set if it's sent to broadcast address.
+    fn name_query(name: &NetbiosName, broadcast: bool) -> Self {
+        let mut header = DnsHeader::new_nbns();
+        header.flags = 0x0100; // RD
+        if broadcast { header.flags |= 0x0010 }; // B
 
-            question,
-            qtype: 0x0021u16.to_be(), // NetBIOS NODE STATUS Resource Record
-            qclass: 0x0001u16.to_be()
+        NbnsQuery {
+            header,
+            question: DnsQuestion {
+                qname: Self::encode_name(name.to_raw()).to_vec(),
+                qtype: Self::NB,
+                qclass: 0x0001
+            },
         }
     }
-    fn as_slice(&self) -> &[u8; Self::SIZE] {
-        unsafe {
-            &*(self as *const Self as *const [u8; Self::SIZE])
+    /// First-level encoding of NetBIOS name, RFC 1001 14.1: every half of byte is added to 'A' and
+    /// the result is single label of 32 bytes.
+    fn encode_name(name: [u8; 16]) -> [u8; 34] {
+        let mut question = [0u8; 34];
+        question[0] = 0x20;
+        for (i, b) in name.iter().enumerate() {
+            question[1 + i * 2] = b'A' + (b >> 4);
+            question[2 + i * 2] = b'A' + (b & 0x0f);
         }
+        question
+    }
+    fn to_packet(&self) -> Vec<u8> {
+        self.header.to_packet(&self.question)
+    }
+
+    /// Sends NODE STATUS REQUEST to `addr` through NBNS socket of `session`, which is shared with other queries.
+    /// Returns node status with number of sent requests.
+    pub async fn send(addr: IpAddr, session: &Session) -> Result<Option<(NodeStatus, u32)>, AppError> {
+
+        let request = Self::new().to_packet();
+
+        let answer = session.nbns_sock.query((addr, Self::PORT).into(), &request, session.config.nbns_timeout, session).await?;
+        let Some((buff, attempts)) = answer else { return Ok(None) };
+
+        let status = NodeStatus::from_response(&buff).ok_or(AppError::InvalidResponseNbns)?;
+        Ok(Some((status, attempts)))
+    }
+
+    /// Sends NODE STATUS REQUEST to broadcast `addr` and calls `on_status` for every host, which
+    /// answered in time, with its address, decoded node status and number of sent requests.
+    /// Unanswered request is sent again as many times as `session` allows, every host is reported once.
+    pub async fn broadcast<F>(addr: Ipv4Addr, session: &Session, mut on_status: F) -> Result<(), AppError>
+    where F: FnMut(Ipv4Addr, Result<NodeStatus, AppError>, u32)
+    {
+        let query = Self::new();
+        let request = query.to_packet();
+
+        let sock = UdpSocket::bind("0.0.0.0:0").await.map_err(|_| AppError::SocketCreate)?;
+        sock.set_broadcast(true).map_err(|_| AppError::SocketCreate)?;
+
+        let mut answered = Vec::new();
+        query_each(&sock, (addr, Self::PORT).into(), &request, session.config.nbns_timeout, session, |datagram, from, attempt| {
+            let SocketAddr::V4(from) = from else { return Receiving::Continue };
+            if datagram.len() < 2 || datagram[..2] != request[..2] || answered.contains(from.ip()) {
+                return Receiving::Continue;
+            }
+
+            answered.push(*from.ip());
+            let status = NodeStatus::from_response(datagram).ok_or(AppError::InvalidResponseNbns);
+            on_status(*from.ip(), status, attempt);
+            Receiving::Answered
+        }).await
     }
 
-    pub fn send(addr: IpAddr) -> Result<Option<Vec<NbnsAnswer>>, AppError> {
+    /// Sends NAME QUERY REQUEST for `name` to WINS `server`, or to limited broadcast address if
+    /// it's `None`, and collects addresses from every answer received in time.
+    /// Unanswered request is sent again as many times as `session` allows.
+    /// Returns empty list if nobody has the name.
+    pub async fn resolve_name(name: &NetbiosName, server: Option<Ipv4Addr>, session: &Session) -> Result<Vec<NbAddress>, AppError> {
+        let query = Self::name_query(name, server.is_none());
+        let request = query.to_packet();
+        let remote: SocketAddr = (server.unwrap_or(Ipv4Addr::BROADCAST), Self::PORT).into();
 
-        let request = Self::new();
+        let sock = UdpSocket::bind("0.0.0.0:0").await.map_err(|_| AppError::SocketCreate)?;
+        sock.set_broadcast(true).map_err(|_| AppError::SocketCreate)?;
 
-        let buff = query(addr, Self::PORT, request.as_slice())?;
-        if buff.is_none() { return Ok(None) };
-        let buff = buff.unwrap();
-        if buff.len() <= Self::SIZE + Self::MIN_RESPONSE_SIZE { return Err(AppError::InvalidResponseNbns) };
+        let mut addresses = Vec::new();
+        let mut wins_answer = None;
+        query_each(&sock, remote, &request, session.config.nbns_timeout, session, |datagram, _, _| {
+            let Some(response) = Message::parse(datagram) else { return Receiving::Continue };
+            if !response.is_response() || response.id != query.header.trans_id { return Receiving::Continue };
 
-        // response contains request + time to live [0u8; 4] + answer
-        // the next two bytes correspond to the answer size, followed by a one byte count of names
-        // next chunks of 18 bytes represent name [u8; 16] + permanent node flags [u8; 2]
-        // array of names is followed by MAC address [u8; 6]
-        // other data is ignored
-        let (_, mut response) = buff.split_at(Self::SIZE + 4);
-        let data_size: u16 = ((response[0] as u16) << 8) | response[1] as u16;
-        let names_count: u8 = response[2];
-        response = &response[3..]; // move slice start to exclude previously obtained data
+            let negative = response.flags & 0x000f != 0;
+            // WINS server answers only once
+            if server.is_some() {
+                wins_answer = Some(match negative {
+                    true => Ok(Vec::new()),
+                    false => NbAddress::from_response(&response).ok_or(AppError::InvalidResponseNbns),
+                });
+                return Receiving::Done;
+            }
+
+            // broadcast is answered by many hosts, negative or bad answer of one of them
+            // shouldn't discard the others
+            if negative { return Receiving::Continue };
+            let Some(found) = NbAddress::from_response(&response) else { return Receiving::Continue };
+            for address in found {
+                if !addresses.contains(&address) { addresses.push(address) };
+            }
+            Receiving::Answered
+        }).await?;
+
+        wins_answer.unwrap_or(Ok(addresses))
+    }
+}
+
+/// NetBIOS name with service suffix, written as `NAME` or `NAME#20` like in nmblookup.
+/// Suffix is 0x00 (workstation) by default.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetbiosName {
+    pub name: String,
+    pub suffix: u8,
+}
+impl NetbiosName {
+    pub const MAX_SIZE: usize = 15;
+
+    /// Decodes the first label of first-level encoded `name`, RFC 1001 14.1. Following labels are
+    /// NetBIOS scope and are ignored. Returns `None` if the label isn't valid encoded name.
+    pub(crate) fn decode(name: &str) -> Option<Self> {
+        let label = name.split('.').next()?.as_bytes();
+        if label.len() != 32 { return None };
+
+        let mut raw = [0u8; 16];
+        for (i, pair) in label.chunks(2).enumerate() {
+            let (high, low) = (pair[0].checked_sub(b'A')?, pair[1].checked_sub(b'A')?);
+            if high > 0x0f || low > 0x0f { return None };
+            raw[i] = (high << 4) | low;
+        }
+
+        let name: String = raw[..Self::MAX_SIZE].iter()
+            .filter(|b| b.is_ascii_graphic())
+            .map(|b| *b as char)
+            .collect();
+        if name.is_empty() { return None };
+        Some(NetbiosName { name, suffix: raw[15] })
+    }
+
+    /// Name padded with spaces and followed by suffix, names are case-insensitive and sent uppercase.
+    fn to_raw(&self) -> [u8; 16] {
+        let mut raw = [b' '; 16];
+        for (i, b) in self.name.bytes().take(Self::MAX_SIZE).enumerate() {
+            raw[i] = b.to_ascii_uppercase();
+        }
+        raw[15] = self.suffix;
+        raw
+    }
+}
+impl std::fmt::Display for NetbiosName {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}<{:02x}>", self.name, self.suffix)
+    }
+}
+impl std::str::FromStr for NetbiosName {
+    type Err = AppError;
+
+    /// Names are up to 15 ASCII characters without spaces, dots and characters used in addresses.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, suffix) = match s.split_once('#') {
+            Some((name, suffix)) => (name, u8::from_str_radix(suffix, 16).map_err(|_| AppError::ParseName)?),
+            None => (s, 0x00),
+        };
+        let is_valid = |c: char| c.is_ascii_graphic() && !matches!(c, '.' | '/' | '%' | ':' | '*');
+        if name.is_empty() || name.len() > Self::MAX_SIZE || !name.chars().all(is_valid) {
+            return Err(AppError::ParseName);
+        }
+
+        Ok(NetbiosName { name: name.to_string(), suffix })
+    }
+}
+
+/// Owner node type from NB_FLAGS, RFC 1002 4.2.1.3.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeType {
+    /// Broadcast node
+    B,
+    /// Point-to-point node, which uses WINS server
+    P,
+    /// Mixed node, broadcast first and WINS then
+    M,
+    /// Hybrid node, WINS first and broadcast then. Reserved in RFC, but used by Microsoft
+    H,
+}
+impl std::fmt::Display for NodeType {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", match self {
+            NodeType::B => "B",
+            NodeType::P => "P",
+            NodeType::M => "M",
+            NodeType::H => "H",
+        })
+    }
+}
+
+/// Address of the name owner from positive NAME QUERY RESPONSE with its NB_FLAGS.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NbAddress {
+    pub addr: Ipv4Addr,
+    pub node_type: NodeType,
+    /// Name is group name, so many hosts may own it
+    pub group: bool,
+}
+impl NbAddress {
+    /// Decodes RDATA of every NB record in answers. Returns `None` if there are no such records or
+    /// they are malformed.
+    fn from_response(response: &Message) -> Option<Vec<Self>> {
+        let mut addresses = Vec::new();
+        for record in response.answers.iter().filter(|r| r.rtype == NbnsQuery::NB) {
+            let RecordData::Other(data) = &record.data else { return None };
+            addresses.extend(Self::parse(data)?);
+        }
+
+        if addresses.is_empty() { return None };
+        Some(addresses)
+    }
+    /// Decodes RDATA of NB record, which is array of NB_FLAGS: u16 + NB_ADDRESS: [u8; 4].
+    pub(crate) fn parse(data: &[u8]) -> Option<Vec<Self>> {
+        if !data.len().is_multiple_of(6) { return None };
+
+        Some(data.chunks(6)
+            .map(|chunk| {
+                let flags = u16::from_be_bytes([chunk[0], chunk[1]]);
+                NbAddress {
+                    addr: Ipv4Addr::new(chunk[2], chunk[3], chunk[4], chunk[5]),
+                    node_type: match (flags >> 13) & 0x03 {
+                        0 => NodeType::B,
+                        1 => NodeType::P,
+                        2 => NodeType::M,
+                        _ => NodeType::H,
+                    },
+                    group: flags & 0x8000 != 0,
+                }
+            })
+            .collect())
+    }
+}
+
+/// Decoded NODE STATUS RESPONSE, RFC 1002 4.2.18
+pub struct NodeStatus {
+    /// Every name of the node followed by MAC address
+    pub names: Vec<NbnsAnswer>,
+    /// Statistics block, if host sent it completely
+    pub statistics: Option<NbnsStatistics>,
+}
+impl NodeStatus {
+    /// Decodes the first NBSTAT record of DNS-like `response`. Returns `None`, if there is no such
+    /// record or it's malformed.
+    pub fn from_response(response: &[u8]) -> Option<Self> {
+        let message = Message::parse(response)?;
+        if !message.is_response() { return None };
+
+        message.answers.iter()
+            .filter(|r| r.rtype == NbnsQuery::NBSTAT)
+            .find_map(|r| match &r.data {
+                RecordData::Other(data) => Self::parse(data),
+                _ => None,
+            })
+    }
+    /// Decodes RDATA of NBSTAT record.
+    /// It starts from one byte count of names,
+    /// next chunks of 18 bytes represent name [u8; 16] + name flags [u8; 2],
+    /// array of names is followed by statistics, which starts from MAC address [u8; 6].
+    fn parse(data: &[u8]) -> Option<Self> {
+        let (&names_count, data) = data.split_first()?;
+        let names_size = names_count as usize * 18;
+        let (raw_names, raw_statistics) = data.split_at_checked(names_size)?;
 
         let mut names = Vec::new();
-        for chunk in response[..data_size as usize].chunks(18).take(names_count as usize) {
+        for chunk in raw_names.chunks(18) {
             // [NAME + OPTIONAL_PADDING(0x20)]: [u8; 15] + SERVICE: u8 + FLAGS: [u8; 2] on each 18 bytes chunk
             let name: String = chunk[..=14].iter()
                 .filter_map(|b| {
@@ -79,16 +322,97 @@ impl NbnsQuery {
                 _ => NbnsAnswer::Unique((name, service)),
             })
         };
-        let raw_mac = &response[names_count as usize * 18 .. names_count as usize * 18 + 6];
-        if let Some(mac) = MacAddress::from_bytes(raw_mac) {
+        if let Some(mac) = raw_statistics.get(..6).and_then(MacAddress::from_bytes) {
             names.push(NbnsAnswer::Mac(mac));
         }
 
-        if names.is_empty() { return Err(AppError::InvalidResponseNbns) };
-        Ok(Some(names))
+        if names.is_empty() { return None };
+        Some(NodeStatus {
+            names,
+            statistics: NbnsStatistics::parse(raw_statistics),
+        })
     }
 }
 
+/// Statistics of NetBIOS node from NODE STATUS RESPONSE. Most of implementations fill only
+/// MAC address and leave the rest zeroed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NbnsStatistics {
+    /// MAC address of the adapter
+    pub unit_id: MacAddress,
+    pub jumpers: u8,
+    pub test_result: u8,
+    pub version_number: u16,
+    pub period_of_statistics: u16,
+    pub crc_errors: u16,
+    pub alignment_errors: u16,
+    pub collisions: u16,
+    pub send_aborts: u16,
+    pub good_sends: u32,
+    pub good_receives: u32,
+    pub retransmits: u16,
+    pub no_resource_conditions: u16,
+    pub free_command_blocks: u16,
+    pub total_command_blocks: u16,
+    pub max_total_command_blocks: u16,
+    pub pending_sessions: u16,
+    pub max_pending_sessions: u16,
+    pub max_total_sessions: u16,
+    /// Maximum size of session data packet, which is also the maximum datagram size
+    pub session_data_packet_size: u16,
+}
+impl NbnsStatistics {
+    pub const SIZE: usize = 46;
+
+    /// Decodes statistics block. Returns `None` if it's truncated.
+    fn parse(data: &[u8]) -> Option<Self> {
+        let data: &[u8; Self::SIZE] = data.get(..Self::SIZE)?.try_into().ok()?;
+        let u16_at = |i: usize| u16::from_be_bytes([data[i], data[i + 1]]);
+        let u32_at = |i: usize| u32::from_be_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]);
+
+        Some(NbnsStatistics {
+            unit_id: MacAddress::from_bytes(&data[..6])?,
+            jumpers: data[6],
+            test_result: data[7],
+            version_number: u16_at(8),
+            period_of_statistics: u16_at(10),
+            crc_errors: u16_at(12),
+            alignment_errors: u16_at(14),
+            collisions: u16_at(16),
+            send_aborts: u16_at(18),
+            good_sends: u32_at(20),
+            good_receives: u32_at(24),
+            retransmits: u16_at(28),
+            no_resource_conditions: u16_at(30),
+            free_command_blocks: u16_at(32),
+            total_command_blocks: u16_at(34),
+            max_total_command_blocks: u16_at(36),
+            pending_sessions: u16_at(38),
+            max_pending_sessions: u16_at(40),
+            max_total_sessions: u16_at(42),
+            session_data_packet_size: u16_at(44),
+        })
+    }
+    /// Lines of verbose output.
+    pub(crate) fn verbose_lines(&self) -> [String; 5] {
+        [
+            format!("Jumpers: {:#04x}, test result: {:#04x}, version: {}.{}, period of statistics: {}",
+                self.jumpers, self.test_result, self.version_number >> 8, self.version_number & 0xff,
+                self.period_of_statistics),
+            format!("CRC errors: {}, alignment errors: {}, collisions: {}, send aborts: {}",
+                self.crc_errors, self.alignment_errors, self.collisions, self.send_aborts),
+            format!("Good sends: {}, good receives: {}, retransmits: {}, no resource conditions: {}",
+                self.good_sends, self.good_receives, self.retransmits, self.no_resource_conditions),
+            format!("Command blocks: {} free, {} total, {} max",
+                self.free_command_blocks, self.total_command_blocks, self.max_total_command_blocks),
+            format!("Sessions: {} pending, {} max pending, {} max total, max datagram size: {}",
+                self.pending_sessions, self.max_pending_sessions, self.max_total_sessions,
+                self.session_data_packet_size),
+        ]
+    }
+}
+
+#[derive(Clone, PartialEq, Eq)]
 pub enum NbnsAnswer {
     Unique((String, u8)),
     Group((String, u8)),
@@ -96,6 +420,39 @@ pub enum NbnsAnswer {
     PermanentGroup((String, u8)),
     Mac(MacAddress),
 }
+impl NbnsAnswer {
+    /// NetBIOS name without trailing spaces. `None` for `NbnsAnswer::Mac`.
+    pub fn name(&self) -> Option<&str> {
+        match self {
+            NbnsAnswer::Unique((name, _))
+            | NbnsAnswer::Group((name, _))
+            | NbnsAnswer::Permanent((name, _))
+            | NbnsAnswer::PermanentGroup((name, _)) => Some(name),
+            NbnsAnswer::Mac(_) => None,
+        }
+    }
+    /// The 16th byte of NetBIOS name, which identifies the service. `None` for `NbnsAnswer::Mac`.
+    pub fn service(&self) -> Option<u8> {
+        match self {
+            NbnsAnswer::Unique((_, service))
+            | NbnsAnswer::Group((_, service))
+            | NbnsAnswer::Permanent((_, service))
+            | NbnsAnswer::PermanentGroup((_, service)) => Some(*service),
+            NbnsAnswer::Mac(_) => None,
+        }
+    }
+    /// Meaning of the service byte, which depends on the name and whether it's a group.
+    /// `None` for `NbnsAnswer::Mac`.
+    pub fn service_type(&self) -> Option<NbnsService> {
+        Some(NbnsService::new(self.name()?, self.service()?, self.is_group()))
+    }
+    pub fn is_group(&self) -> bool {
+        matches!(self, NbnsAnswer::Group(_) | NbnsAnswer::PermanentGroup(_))
+    }
+    pub fn is_permanent(&self) -> bool {
+        matches!(self, NbnsAnswer::Permanent(_) | NbnsAnswer::PermanentGroup(_))
+    }
+}
 impl std::fmt::Display for NbnsAnswer {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
@@ -111,11 +468,290 @@ impl std::fmt::Display for NbnsAnswer {
 impl std::fmt::Debug for NbnsAnswer {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            NbnsAnswer::Unique((name, service))         => { write!(f, "{} Service: {:x}", name, service) },
-            NbnsAnswer::Group((name, service))          => { write!(f, "{} (Group) Service: {:x}", name, service) },
-            NbnsAnswer::Permanent((name, service))      => { write!(f, "{} (Permanent name) Service: {:x}", name, service) },
-            NbnsAnswer::PermanentGroup((name, service)) => { write!(f, "{} (Permanent group) Service: {:x}", name, service) },
-            NbnsAnswer::Mac(mac)                        => { write!(f, "MAC address: {}", mac) }
+            NbnsAnswer::Unique((name, service))         => { write!(f, "{} Service: {:x}", name, service)? },
+            NbnsAnswer::Group((name, service))          => { write!(f, "{} (Group) Service: {:x}", name, service)? },
+            NbnsAnswer::Permanent((name, service))      => { write!(f, "{} (Permanent name) Service: {:x}", name, service)? },
+            NbnsAnswer::PermanentGroup((name, service)) => { write!(f, "{} (Permanent group) Service: {:x}", name, service)? },
+            NbnsAnswer::Mac(mac)                        => { return write!(f, "MAC address: {}", mac) }
+        }
+        match self.service_type() {
+            Some(NbnsService::Other(_)) | None => Ok(()),
+            Some(service) => write!(f, " ({})", service),
+        }
+    }
+}
+
+/// Well-known services, identified by the 16th byte of NetBIOS name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NbnsService {
+    /// 0x00 unique
+    Workstation,
+    /// 0x00 group, which is workgroup or NT domain name
+    DomainName,
+    /// 0x01 group `__MSBROWSE__`, registered by master browser
+    MsBrowse,
+    /// 0x03
+    Messenger,
+    /// 0x06
+    RasServer,
+    /// 0x1B unique
+    DomainMasterBrowser,
+    /// 0x1C group
+    DomainControllers,
+    /// 0x1D unique
+    MasterBrowser,
+    /// 0x1E group
+    BrowserElection,
+    /// 0x1F
+    NetDde,
+    /// 0x20
+    FileServer,
+    /// 0x21
+    RasClient,
+    /// 0xBE
+    NetworkMonitorAgent,
+    /// 0xBF
+    NetworkMonitorApplication,
+    Other(u8),
+}
+impl NbnsService {
+    pub fn new(name: &str, service: u8, is_group: bool) -> Self {
+        match (service, is_group) {
+            (0x00, false) => NbnsService::Workstation,
+            (0x00, true)  => NbnsService::DomainName,
+            (0x01, true) if name == "__MSBROWSE__" => NbnsService::MsBrowse,
+            (0x03, _)     => NbnsService::Messenger,
+            (0x06, _)     => NbnsService::RasServer,
+            (0x1b, false) => NbnsService::DomainMasterBrowser,
+            (0x1c, true)  => NbnsService::DomainControllers,
+            (0x1d, false) => NbnsService::MasterBrowser,
+            (0x1e, true)  => NbnsService::BrowserElection,
+            (0x1f, _)     => NbnsService::NetDde,
+            (0x20, _)     => NbnsService::FileServer,
+            (0x21, _)     => NbnsService::RasClient,
+            (0xbe, _)     => NbnsService::NetworkMonitorAgent,
+            (0xbf, _)     => NbnsService::NetworkMonitorApplication,
+            (s, _)        => NbnsService::Other(s),
+        }
+    }
+    /// Role of the host, which registered name with this service.
+    pub fn role(&self) -> Option<HostRole> {
+        match self {
+            NbnsService::Workstation => Some(HostRole::Workstation),
+            NbnsService::FileServer => Some(HostRole::FileServer),
+            NbnsService::DomainControllers => Some(HostRole::DomainController),
+            NbnsService::DomainMasterBrowser => Some(HostRole::DomainMasterBrowser),
+            NbnsService::MasterBrowser | NbnsService::MsBrowse => Some(HostRole::MasterBrowser),
+            NbnsService::RasServer => Some(HostRole::RasServer),
+            _ => None,
+        }
+    }
+}
+impl std::fmt::Display for NbnsService {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            NbnsService::Workstation               => write!(f, "Workstation"),
+            NbnsService::DomainName                => write!(f, "Domain name"),
+            NbnsService::MsBrowse                  => write!(f, "Master browser announcements"),
+            NbnsService::Messenger                 => write!(f, "Messenger"),
+            NbnsService::RasServer                 => write!(f, "RAS server"),
+            NbnsService::DomainMasterBrowser       => write!(f, "Domain master browser"),
+            NbnsService::DomainControllers         => write!(f, "Domain controllers"),
+            NbnsService::MasterBrowser             => write!(f, "Master browser"),
+            NbnsService::BrowserElection           => write!(f, "Browser elections"),
+            NbnsService::NetDde                    => write!(f, "NetDDE"),
+            NbnsService::FileServer                => write!(f, "File server"),
+            NbnsService::RasClient                 => write!(f, "RAS client"),
+            NbnsService::NetworkMonitorAgent       => write!(f, "Network monitor agent"),
+            NbnsService::NetworkMonitorApplication => write!(f, "Network monitor application"),
+            NbnsService::Other(s)                  => write!(f, "Unknown service {:#04x}", s),
+        }
+    }
+}
+
+/// What host does in the network, derived from services of its NetBIOS names.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HostRole {
+    Workstation,
+    FileServer,
+    DomainController,
+    DomainMasterBrowser,
+    MasterBrowser,
+    RasServer,
+}
+impl std::fmt::Display for HostRole {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", match self {
+            HostRole::Workstation => "workstation",
+            HostRole::FileServer => "file server",
+            HostRole::DomainController => "domain controller",
+            HostRole::DomainMasterBrowser => "domain master browser",
+            HostRole::MasterBrowser => "master browser",
+            HostRole::RasServer => "RAS server",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn node_status_request_packet() {
+        let mut query = NbnsQuery::new();
+        query.header.trans_id = 0x1234;
+
+        let mut expected = b"\x12\x34\x00\x00\x00\x01\x00\x00\x00\x00\x00\x00\x20CK".to_vec();
+        expected.extend_from_slice(&[b'A'; 30]);
+        expected.extend_from_slice(b"\x00\x00\x21\x00\x01");
+        assert_eq!(query.to_packet(), expected);
+    }
+
+    #[test]
+    fn name_query_packet() {
+        let mut query = NbnsQuery::name_query(&"filesrv01#20".parse().unwrap(), true);
+        query.header.trans_id = 0xabcd;
+
+        let mut expected = b"\xab\xcd\x01\x10\x00\x01\x00\x00\x00\x00\x00\x00\x20EGEJEMEFFDFCFGDADB".to_vec();
+        expected.extend_from_slice(b"CACACACACACACA\x00\x00\x20\x00\x01");
+        assert_eq!(query.to_packet(), expected);
+    }
+
+    /// NODE STATUS RESPONSE with two names and statistics, in which every field has other value.
+    fn node_status_response(statistics_size: usize) -> Vec<u8> {
+        let mut data = vec![2];
+        data.extend_from_slice(b"WIN10-PC       \x00\x04\x00");
+        data.extend_from_slice(b"WORKGROUP      \x00\x84\x00");
+        data.extend_from_slice(&[0x00, 0x15, 0x5d, 0x01, 0x02, 0x03, 0x11, 0x22]);
+        data.extend(3u8..=40);
+        data.truncate(1 + 2 * 18 + statistics_size);
+
+        let mut packet = b"\x12\x34\x84\x00\x00\x00\x00\x01\x00\x00\x00\x00".to_vec();
+        packet.extend_from_slice(&NbnsQuery::new().question.qname);
+        packet.extend_from_slice(&[0x00, 0x21, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00]);
+        packet.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        packet.extend_from_slice(&data);
+        packet
+    }
+
+    #[test]
+    fn node_status_response_names() {
+        let status = NodeStatus::from_response(&node_status_response(NbnsStatistics::SIZE)).unwrap();
+        let mac = MacAddress::from_bytes(&[0x00, 0x15, 0x5d, 0x01, 0x02, 0x03]).unwrap();
+        assert_eq!(status.names, [
+            NbnsAnswer::Unique(("WIN10-PC".to_string(), 0x00)),
+            NbnsAnswer::Group(("WORKGROUP".to_string(), 0x00)),
+            NbnsAnswer::Mac(mac),
+        ]);
+    }
+
+    #[test]
+    fn node_status_response_statistics() {
+        let status = NodeStatus::from_response(&node_status_response(NbnsStatistics::SIZE)).unwrap();
+        let statistics = status.statistics.unwrap();
+        assert_eq!(statistics, NbnsStatistics {
+            unit_id: MacAddress::from_bytes(&[0x00, 0x15, 0x5d, 0x01, 0x02, 0x03]).unwrap(),
+            jumpers: 0x11,
+            test_result: 0x22,
+            version_number: 0x0304,
+            period_of_statistics: 0x0506,
+            crc_errors: 0x0708,
+            alignment_errors: 0x090a,
+            collisions: 0x0b0c,
+            send_aborts: 0x0d0e,
+            good_sends: 0x0f101112,
+            good_receives: 0x13141516,
+            retransmits: 0x1718,
+            no_resource_conditions: 0x191a,
+            free_command_blocks: 0x1b1c,
+            total_command_blocks: 0x1d1e,
+            max_total_command_blocks: 0x1f20,
+            pending_sessions: 0x2122,
+            max_pending_sessions: 0x2324,
+            max_total_sessions: 0x2526,
+            session_data_packet_size: 0x2728,
+        });
+    }
+
+    #[test]
+    fn node_status_response_truncated_statistics() {
+        // only MAC address and a part of statistics
+        let status = NodeStatus::from_response(&node_status_response(10)).unwrap();
+        assert!(status.statistics.is_none());
+        assert_eq!(status.names.len(), 3);
+
+        // names without MAC address
+        let status = NodeStatus::from_response(&node_status_response(0)).unwrap();
+        assert!(status.statistics.is_none());
+        assert_eq!(status.names.len(), 2);
+    }
+
+    #[test]
+    fn netbios_name_round_trip() {
+        for name in ["FILESRV01#20", "WORKGROUP#1e", "A", "__MSBROWSE__#01"] {
+            let name: NetbiosName = name.parse().unwrap();
+            let encoded = NbnsQuery::encode_name(name.to_raw());
+            assert_eq!((encoded[0], encoded[33]), (0x20, 0x00));
+
+            let label = std::str::from_utf8(&encoded[1..33]).unwrap();
+            assert_eq!(NetbiosName::decode(label), Some(name.clone()));
+            // NetBIOS scope is ignored
+            assert_eq!(NetbiosName::decode(&format!("{}.corp.local", label)), Some(name));
+        }
+    }
+
+    #[test]
+    fn netbios_name_decode_invalid() {
+        assert_eq!(NetbiosName::decode("EGEJEMEF"), None);
+        // Q is out of A-P
+        assert_eq!(NetbiosName::decode(&"Q".repeat(32)), None);
+        // only spaces
+        assert_eq!(NetbiosName::decode(&"CA".repeat(16)), None);
+    }
+
+    #[test]
+    fn nb_address_flags() {
+        let data = [
+            0x00, 0x00, 10, 0, 0, 1,
+            0x20, 0x00, 10, 0, 0, 2,
+            0x40, 0x00, 10, 0, 0, 3,
+            0x60, 0x00, 10, 0, 0, 4,
+            0x80, 0x00, 10, 0, 0, 5,
+            0xe0, 0x00, 10, 0, 0, 6,
+        ];
+        let flags: Vec<(Ipv4Addr, NodeType, bool)> = NbAddress::parse(&data).unwrap().iter()
+            .map(|a| (a.addr, a.node_type, a.group))
+            .collect();
+        assert_eq!(flags, [
+            (Ipv4Addr::new(10, 0, 0, 1), NodeType::B, false),
+            (Ipv4Addr::new(10, 0, 0, 2), NodeType::P, false),
+            (Ipv4Addr::new(10, 0, 0, 3), NodeType::M, false),
+            (Ipv4Addr::new(10, 0, 0, 4), NodeType::H, false),
+            (Ipv4Addr::new(10, 0, 0, 5), NodeType::B, true),
+            (Ipv4Addr::new(10, 0, 0, 6), NodeType::H, true),
+        ]);
+
+        assert_eq!(NbAddress::parse(&data[..5]), None);
+    }
+
+    #[test]
+    fn services_and_roles() {
+        let cases = [
+            ("WIN10-PC", 0x00, false, NbnsService::Workstation, Some(HostRole::Workstation)),
+            ("WORKGROUP", 0x00, true, NbnsService::DomainName, None),
+            ("WIN10-PC", 0x03, false, NbnsService::Messenger, None),
+            ("CORP", 0x1b, false, NbnsService::DomainMasterBrowser, Some(HostRole::DomainMasterBrowser)),
+            ("CORP", 0x1c, true, NbnsService::DomainControllers, Some(HostRole::DomainController)),
+            ("WORKGROUP", 0x1d, false, NbnsService::MasterBrowser, Some(HostRole::MasterBrowser)),
+            ("WORKGROUP", 0x1e, true, NbnsService::BrowserElection, None),
+            ("WIN10-PC", 0x20, false, NbnsService::FileServer, Some(HostRole::FileServer)),
+            ("__MSBROWSE__", 0x01, true, NbnsService::MsBrowse, Some(HostRole::MasterBrowser)),
+            ("WIN10-PC", 0x01, true, NbnsService::Other(0x01), None),
+        ];
+        for (name, service, is_group, expected, role) in cases {
+            let found = NbnsService::new(name, service, is_group);
+            assert_eq!(found, expected, "{}<{:02x}> group: {}", name, service, is_group);
+            assert_eq!(found.role(), role, "{}<{:02x}> group: {}", name, service, is_group);
         }
     }
 }
@@ -0,0 +1,189 @@
+// Kernel neighbor table (ARP and NDP caches)
+// https://man7.org/linux/man-pages/man7/rtnetlink.7.html
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use crate::MacAddress;
+
+/// Entry of kernel neighbor table: address of host on the local link.
+pub struct Neighbor {
+    pub addr: IpAddr,
+    /// Index of network interface, where the host is
+    pub ifindex: u32,
+    /// Link-layer address, missing on interfaces without ARP, like loopback
+    pub mac: Option<MacAddress>,
+}
+
+/// Reads IPv4 and IPv6 neighbors known by the kernel. Incomplete and failed entries are skipped.
+/// Always returns empty list, if the table can't be read or it's not supported by the system.
+pub fn neighbor_table() -> Vec<Neighbor> {
+    #[cfg(target_os = "linux")]
+    {
+        netlink::dump_neighbors().unwrap_or_default()
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        Vec::new()
+    }
+}
+
+/// Snapshot of neighbor table shared by every host of one scan, so the table isn't read for each
+/// of them. It's read again on a miss, if the snapshot is older than `NeighborCache::TTL`, because
+/// answered hosts are added to the table during the scan.
+pub struct NeighborCache {
+    neighbors: Vec<Neighbor>,
+    taken: Option<std::time::Instant>,
+}
+impl NeighborCache {
+    pub const TTL: std::time::Duration = std::time::Duration::from_secs(1);
+
+    /// Empty cache, the table is read on the first lookup.
+    pub fn new() -> Self {
+        NeighborCache { neighbors: Vec::new(), taken: None }
+    }
+    /// MAC address of `addr` from the snapshot, or from the fresh table if it's not found.
+    pub fn mac(&mut self, addr: IpAddr) -> Option<MacAddress> {
+        if let Some(mac) = self.find(addr) { return Some(mac) };
+        if self.taken.is_some_and(|t| t.elapsed() < Self::TTL) { return None };
+
+        self.neighbors = neighbor_table();
+        self.taken = Some(std::time::Instant::now());
+        self.find(addr)
+    }
+    fn find(&self, addr: IpAddr) -> Option<MacAddress> {
+        self.neighbors.iter().find(|n| n.addr == addr).and_then(|n| n.mac)
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod netlink {
+    use super::*;
+
+    const RTM_NEWNEIGH: u16 = 28;
+    const RTM_GETNEIGH: u16 = 30;
+    const NLMSG_ERROR: u16 = 2;
+    const NLMSG_DONE: u16 = 3;
+    const NLM_F_REQUEST: u16 = 0x01;
+    const NLM_F_DUMP: u16 = 0x300;
+    const NDA_DST: u16 = 1;
+    const NDA_LLADDR: u16 = 2;
+    const NUD_INCOMPLETE: u16 = 0x01;
+    const NUD_FAILED: u16 = 0x20;
+
+    const NLMSG_HEADER_SIZE: usize = 16;
+    const NDMSG_SIZE: usize = 12;
+    const RECV_BUFF_SIZE: usize = 32 * 1024;
+
+    /// Netlink socket, which is closed on `drop`.
+    struct NetlinkSocket (i32);
+    impl Drop for NetlinkSocket {
+        fn drop(&mut self) {
+            // SAFETY: descriptor is owned by `NetlinkSocket` and isn't used after closing
+            unsafe { libc::close(self.0) };
+        }
+    }
+
+    /// Sends RTM_GETNEIGH dump request and collects every RTM_NEWNEIGH message of the answer.
+    pub fn dump_neighbors() -> Option<Vec<Neighbor>> {
+        // SAFETY: plain system call without pointers
+        let fd = unsafe { libc::socket(libc::AF_NETLINK, libc::SOCK_RAW | libc::SOCK_CLOEXEC, libc::NETLINK_ROUTE) };
+        if fd < 0 { return None };
+        let sock = NetlinkSocket(fd);
+
+        // nlmsghdr: length: u32, type: u16, flags: u16, sequence number: u32, port ID: u32
+        // ndmsg: family: u8, padding: [u8; 3], interface index: i32, state: u16, flags: u8, type: u8
+        // fields are in host byte order
+        let mut request = Vec::with_capacity(NLMSG_HEADER_SIZE + NDMSG_SIZE);
+        request.extend_from_slice(&((NLMSG_HEADER_SIZE + NDMSG_SIZE) as u32).to_ne_bytes());
+        request.extend_from_slice(&RTM_GETNEIGH.to_ne_bytes());
+        request.extend_from_slice(&(NLM_F_REQUEST | NLM_F_DUMP).to_ne_bytes());
+        request.extend_from_slice(&1u32.to_ne_bytes());
+        request.extend_from_slice(&0u32.to_ne_bytes());
+        request.push(libc::AF_UNSPEC as u8); // both IPv4 and IPv6
+        request.extend_from_slice(&[0u8; NDMSG_SIZE - 1]);
+
+        // SAFETY: zeroed sockaddr_nl is valid, it addresses the kernel
+        let mut kernel: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+        kernel.nl_family = libc::AF_NETLINK as u16;
+        // SAFETY: pointers and sizes correspond to `request` and `kernel`, which outlive the call
+        let sent = unsafe {
+            libc::sendto(
+                sock.0,
+                request.as_ptr() as *const libc::c_void,
+                request.len(),
+                0,
+                &kernel as *const libc::sockaddr_nl as *const libc::sockaddr,
+                std::mem::size_of::<libc::sockaddr_nl>() as u32,
+            )
+        };
+        if sent < 0 { return None };
+
+        let mut neighbors = Vec::new();
+        let mut buff = vec![0u8; RECV_BUFF_SIZE];
+        loop {
+            // SAFETY: pointer and size correspond to `buff`
+            let size = unsafe { libc::recv(sock.0, buff.as_mut_ptr() as *mut libc::c_void, buff.len(), 0) };
+            if size <= 0 { return None };
+
+            let mut messages = &buff[..size as usize];
+            while messages.len() >= NLMSG_HEADER_SIZE {
+                let length = u32::from_ne_bytes(messages[0..4].try_into().ok()?) as usize;
+                let msg_type = u16::from_ne_bytes(messages[4..6].try_into().ok()?);
+                if length < NLMSG_HEADER_SIZE || length > messages.len() { return None };
+
+                match msg_type {
+                    NLMSG_DONE => return Some(neighbors),
+                    NLMSG_ERROR => return None,
+                    RTM_NEWNEIGH => {
+                        if let Some(n) = parse_neighbor(&messages[NLMSG_HEADER_SIZE..length]) {
+                            neighbors.push(n);
+                        }
+                    },
+                    _ => {},
+                }
+
+                messages = messages.get(align(length)..).unwrap_or_default();
+            }
+        }
+    }
+
+    /// Parses ndmsg followed by attributes.
+    fn parse_neighbor(msg: &[u8]) -> Option<Neighbor> {
+        if msg.len() < NDMSG_SIZE { return None };
+        let ifindex = i32::from_ne_bytes(msg[4..8].try_into().ok()?) as u32;
+        let state = u16::from_ne_bytes(msg[8..10].try_into().ok()?);
+        if state & (NUD_INCOMPLETE | NUD_FAILED) != 0 { return None };
+
+        let mut addr = None;
+        let mut mac = None;
+
+        // rtattr: length: u16, type: u16, data aligned to 4 bytes
+        let mut attrs = &msg[NDMSG_SIZE..];
+        while attrs.len() >= 4 {
+            let length = u16::from_ne_bytes([attrs[0], attrs[1]]) as usize;
+            let attr_type = u16::from_ne_bytes([attrs[2], attrs[3]]);
+            if length < 4 || length > attrs.len() { break };
+            let data = &attrs[4..length];
+
+            if attr_type == NDA_DST {
+                addr = match data.len() {
+                    4  => Some(IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(data).ok()?))),
+                    16 => Some(IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(data).ok()?))),
+                    _ => None,
+                };
+            }
+            if attr_type == NDA_LLADDR {
+                // all-zero address is used by NOARP entries
+                mac = MacAddress::from_bytes(data).filter(|m| m.octets() != [0; 6]);
+            }
+
+            attrs = attrs.get(align(length)..).unwrap_or_default();
+        }
+
+        Some(Neighbor { addr: addr?, ifindex, mac })
+    }
+
+    /// Netlink messages and attributes are aligned to 4 bytes.
+    fn align(length: usize) -> usize {
+        (length + 3) & !3
+    }
+}
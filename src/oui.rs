@@ -0,0 +1,129 @@
+// Vendors of network adapters by the first three octets of MAC address (OUI)
+// https://standards-oui.ieee.org/oui/oui.txt
+
+use std::collections::HashMap;
+use crate::{AppError, MacAddress};
+
+/// Small list of vendors common in home and office networks, the full IEEE database may be loaded
+/// with `OuiDatabase::load`.
+const BUILTIN: &[([u8; 3], &str)] = &[
+    ([0x00, 0x00, 0x0c], "Cisco Systems"),
+    ([0x00, 0x03, 0x93], "Apple"),
+    ([0x00, 0x05, 0x69], "VMware"),
+    ([0x00, 0x0c, 0x29], "VMware"),
+    ([0x00, 0x0d, 0xb9], "PC Engines"),
+    ([0x00, 0x0e, 0x58], "Sonos"),
+    ([0x00, 0x11, 0x32], "Synology"),
+    ([0x00, 0x14, 0x22], "Dell"),
+    ([0x00, 0x15, 0x5d], "Microsoft"),
+    ([0x00, 0x16, 0x3e], "Xensource"),
+    ([0x00, 0x17, 0x88], "Philips Lighting"),
+    ([0x00, 0x1a, 0x11], "Google"),
+    ([0x00, 0x1b, 0x21], "Intel"),
+    ([0x00, 0x1b, 0x63], "Apple"),
+    ([0x00, 0x1c, 0x42], "Parallels"),
+    ([0x00, 0x1d, 0xd8], "Microsoft"),
+    ([0x00, 0x25, 0x00], "Apple"),
+    ([0x00, 0x27, 0x22], "Ubiquiti"),
+    ([0x00, 0x50, 0x56], "VMware"),
+    ([0x00, 0x50, 0xf2], "Microsoft"),
+    ([0x00, 0x80, 0x77], "Brother"),
+    ([0x00, 0xe0, 0x4c], "Realtek"),
+    ([0x08, 0x00, 0x27], "PCS Systemtechnik (VirtualBox)"),
+    ([0x18, 0xb4, 0x30], "Nest Labs"),
+    ([0x24, 0x5e, 0xbe], "QNAP Systems"),
+    ([0x44, 0x65, 0x0d], "Amazon"),
+    ([0xb8, 0x27, 0xeb], "Raspberry Pi Foundation"),
+    ([0xdc, 0xa6, 0x32], "Raspberry Pi Trading"),
+    ([0xe4, 0x5f, 0x01], "Raspberry Pi Trading"),
+    ([0xf0, 0x9f, 0xc2], "Ubiquiti"),
+    ([0xf4, 0xf5, 0xd8], "Google"),
+];
+
+/// Vendor names by OUI.
+#[derive(Debug, Clone)]
+pub struct OuiDatabase {
+    vendors: HashMap<[u8; 3], String>,
+}
+impl OuiDatabase {
+    /// Compiled-in list of common vendors.
+    pub fn builtin() -> Self {
+        OuiDatabase {
+            vendors: BUILTIN.iter().map(|(oui, vendor)| (*oui, vendor.to_string())).collect(),
+        }
+    }
+    /// Reads file in IEEE `oui.txt` format (`00-1A-11   (hex)    Google, Inc.`) or Wireshark `manuf`
+    /// format (`00:1A:11<TAB>Google<TAB>Google, Inc.`) and adds its vendors to the compiled-in list.
+    /// Lines not starting with OUI are ignored. Returns `AppError::LoadOui` if the file can't be read.
+    pub fn load(path: &std::path::Path) -> Result<Self, AppError> {
+        let text = std::fs::read_to_string(path).map_err(|_| AppError::LoadOui)?;
+
+        let mut database = Self::builtin();
+        for line in text.lines() {
+            if let Some((oui, vendor)) = Self::parse_line(line) {
+                database.vendors.insert(oui, vendor.to_string());
+            }
+        }
+        Ok(database)
+    }
+    fn parse_line(line: &str) -> Option<([u8; 3], &str)> {
+        let (prefix, rest) = line.trim_start().split_once(char::is_whitespace)?;
+
+        let digits: String = prefix.chars().filter(|c| !matches!(c, '-' | ':' | '.')).collect();
+        if digits.len() != 6 { return None };
+        let value = u32::from_str_radix(&digits, 16).ok()?;
+        let [_, a, b, c] = value.to_be_bytes();
+
+        // IEEE lists every OUI twice, with "(hex)" and "(base 16)", and manuf has short name before the full one
+        let rest = rest.trim_start();
+        let rest = rest.strip_prefix("(hex)").or(rest.strip_prefix("(base 16)")).unwrap_or(rest);
+        let vendor = rest.split('\t').map(str::trim).rfind(|s| !s.is_empty())?;
+
+        Some(([a, b, c], vendor))
+    }
+
+    /// Vendor of the adapter with `mac` address. Locally administered addresses aren't assigned by
+    /// vendors, so they are never found.
+    pub fn lookup(&self, mac: &MacAddress) -> Option<&str> {
+        if mac.is_locally_administered() { return None };
+        self.vendors.get(&mac.oui()).map(|v| v.as_str())
+    }
+}
+impl Default for OuiDatabase {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_ieee_lines() {
+        assert_eq!(OuiDatabase::parse_line("00-1A-11   (hex)\t\tGoogle, Inc."), Some(([0x00, 0x1a, 0x11], "Google, Inc.")));
+        assert_eq!(OuiDatabase::parse_line("001A11     (base 16)\t\tGoogle, Inc."), Some(([0x00, 0x1a, 0x11], "Google, Inc.")));
+        assert_eq!(OuiDatabase::parse_line("OUI/MA-L                                                    Organization"), None);
+        assert_eq!(OuiDatabase::parse_line("\t\t\t\tMountain View  CA  94043"), None);
+        assert_eq!(OuiDatabase::parse_line(""), None);
+    }
+
+    #[test]
+    fn parse_manuf_lines() {
+        assert_eq!(OuiDatabase::parse_line("00:1A:11\tGoogle\tGoogle, Inc."), Some(([0x00, 0x1a, 0x11], "Google, Inc.")));
+        assert_eq!(OuiDatabase::parse_line("B8:27:EB\tRaspberr"), Some(([0xb8, 0x27, 0xeb], "Raspberr")));
+        assert_eq!(OuiDatabase::parse_line("# Wireshark manuf file"), None);
+        // longer prefixes of MA-M and MA-S blocks aren't supported
+        assert_eq!(OuiDatabase::parse_line("00:1B:C5:00:00:00/36\tConverge\tConverging Systems Inc"), None);
+    }
+
+    #[test]
+    fn lookup_skips_locally_administered() {
+        let database = OuiDatabase::builtin();
+        let mac = MacAddress::from_bytes(&[0xb8, 0x27, 0xeb, 0x01, 0x02, 0x03]).unwrap();
+        assert_eq!(database.lookup(&mac), Some("Raspberry Pi Foundation"));
+
+        let local = MacAddress::from_bytes(&[0xba, 0x27, 0xeb, 0x01, 0x02, 0x03]).unwrap();
+        assert_eq!(database.lookup(&local), None);
+    }
+}
@@ -10,3 +10,67 @@ impl AppendNewline for String {
         self.push_str(NEWLINE);
     }
 }
+
+/// Quotes `s` and escapes it to be used as JSON string.
+pub fn json_string(s: &str) -> String {
+    let mut res = String::with_capacity(s.len() + 2);
+    res.push('"');
+    for c in s.chars() {
+        match c {
+            '"'  => res.push_str("\\\""),
+            '\\' => res.push_str("\\\\"),
+            '\n' => res.push_str("\\n"),
+            '\r' => res.push_str("\\r"),
+            '\t' => res.push_str("\\t"),
+            c if c.is_control() => res.push_str(&format!("\\u{:04x}", c as u32)),
+            c => res.push(c),
+        }
+    }
+    res.push('"');
+    res
+}
+
+/// `null` for `None`, JSON string otherwise.
+pub fn json_optional_string(s: Option<&str>) -> String {
+    s.map_or("null".to_string(), json_string)
+}
+
+/// Quotes `s` to be used as CSV or TSV field, if it contains `separator`, quotes or line breaks.
+pub fn csv_field(s: &str, separator: char) -> String {
+    if s.contains([separator, '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_string_escapes() {
+        assert_eq!(json_string("HOST"), "\"HOST\"");
+        assert_eq!(json_string("a\"b\\c"), "\"a\\\"b\\\\c\"");
+        assert_eq!(json_string("a\nb\rc\td"), "\"a\\nb\\rc\\td\"");
+        assert_eq!(json_string("\u{0}\u{1f}\u{7f}"), "\"\\u0000\\u001f\\u007f\"");
+        assert_eq!(json_string("домен"), "\"домен\"");
+    }
+
+    #[test]
+    fn json_optional_string_null() {
+        assert_eq!(json_optional_string(None), "null");
+        assert_eq!(json_optional_string(Some("x")), "\"x\"");
+    }
+
+    #[test]
+    fn csv_field_quoting() {
+        assert_eq!(csv_field("HOST", ','), "HOST");
+        assert_eq!(csv_field("a,b", ','), "\"a,b\"");
+        assert_eq!(csv_field("a,b", '\t'), "a,b");
+        assert_eq!(csv_field("a\tb", '\t'), "\"a\tb\"");
+        assert_eq!(csv_field("say \"hi\"", ','), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("a\nb", ','), "\"a\nb\"");
+        assert_eq!(csv_field("a\rb", '\t'), "\"a\rb\"");
+    }
+}